@@ -2,8 +2,122 @@
 extern crate serde;
 extern crate ic_cdk_macros;
 extern crate ic_cdk;
+use candid::Principal;
+#[cfg(not(test))]
 use ic_cdk::api::time;
-use std::collections::HashMap;
+#[cfg(test)]
+use tests::time;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::writer::Writer;
+use ic_stable_structures::{DefaultMemoryImpl, Memory as StableMemory};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+
+type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Stable memory region the system state is written to across upgrades
+const UPGRADES_MEMORY_ID: MemoryId = MemoryId::new(0);
+
+// Version of the saved state layout, bumped when a release has to migrate the records it saved
+const STATE_VERSION: u32 = 1;
+
+// ic_cdk::api::time() is in nanoseconds
+const NANOS_PER_HOUR: u64 = 60 * 60 * 1_000_000_000;
+const NANOS_PER_DAY: u64 = 24 * NANOS_PER_HOUR;
+
+// Longest gap allowed between two doses of a medication
+const MAX_DOSE_FREQUENCY_HOURS: u32 = 24 * 365;
+
+// Longest age or interval, in days, a vaccine protocol can use
+const MAX_PROTOCOL_DAYS: u32 = 20 * 365;
+
+// Longest withdrawal period in days a medication product can have
+const MAX_WITHDRAWAL_DAYS: u32 = 2 * 365;
+
+// Deepest pedigree or descendant query, in generations
+const MAX_PEDIGREE_GENERATIONS: u32 = 16;
+
+// Most offspring a single birth can record
+const MAX_LITTER_SIZE: usize = 24;
+
+// Number of candidates returned by suggest_mates when no limit is given
+const DEFAULT_MATE_SUGGESTIONS: u32 = 20;
+
+// Average lengths of a month and a year in days, used to express ages
+const DAYS_PER_MONTH: f64 = 30.4375;
+const DAYS_PER_YEAR: f64 = 365.25;
+
+// Upper bounds, in days, of the age brackets of the age histogram. The last bracket is open ended.
+const AGE_BRACKETS: &[(&str, Option<u32>)] = &[
+    ("0-3 months", Some(91)),
+    ("3-6 months", Some(182)),
+    ("6-12 months", Some(365)),
+    ("1-2 years", Some(2 * 365)),
+    ("2-5 years", Some(5 * 365)),
+    ("5+ years", None),
+];
+
+// Page size of list_animals and get_event_logs when the caller gives none, and the largest page they return
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 200;
+
+// Most tags an animal can carry and the longest tag
+const MAX_TAGS: usize = 32;
+const MAX_TAG_LENGTH: usize = 64;
+
+// Longest external identifier, e.g. an ear tag number
+const MAX_IDENTIFIER_LENGTH: usize = 64;
+
+// Longest name of a barn, pen or paddock
+const MAX_LOCATION_NAME_LENGTH: usize = 100;
+
+// Longest name of a herd group, and the most animals a batch call acts on
+const MAX_GROUP_NAME_LENGTH: usize = 100;
+const MAX_BATCH_SIZE: usize = 500;
+
+// Longest feed name, most feeds in a ration and the highest energy content of a feed in MJ per kg of dry matter
+const MAX_FEED_NAME_LENGTH: usize = 100;
+const MAX_RATION_COMPONENTS: usize = 32;
+const MAX_FEED_ENERGY: f32 = 50.0;
+
+// Entries kept in the event log by default, and the most a retention policy can keep
+const DEFAULT_EVENT_LOG_ENTRIES: u64 = 100_000;
+const MAX_EVENT_LOG_ENTRIES: u64 = 500_000;
+
+// Longest note a vet can attach to a health status change
+const MAX_NOTE_LENGTH: usize = 2_000;
+
+// Limits of a health record: the longest diagnosis and the most symptoms and lab results it can list
+const MAX_DIAGNOSIS_LENGTH: usize = 200;
+const MAX_SYMPTOMS: usize = 32;
+const MAX_LAB_RESULTS: usize = 32;
+
+// Body temperatures in degrees Celsius a health record can hold
+const MIN_BODY_TEMPERATURE: f32 = 30.0;
+const MAX_BODY_TEMPERATURE: f32 = 45.0;
+
+// Hours an alert for a sick animal can stay unacknowledged before it is escalated, by default and at most
+const DEFAULT_ALERT_ESCALATION_HOURS: u32 = 24;
+const MAX_ALERT_ESCALATION_HOURS: u32 = 24 * 30;
+
+// Somatic cells per ml of milk from which a milking raises a mastitis alert, by default and the range it can be set to
+const DEFAULT_MASTITIS_CELL_COUNT: u32 = 200_000;
+const MIN_MASTITIS_CELL_COUNT: u32 = 50_000;
+const MAX_MASTITIS_CELL_COUNT: u32 = 1_000_000;
+
+// Most litres a single milking can yield and the highest fat and protein percentages a milk sample can hold
+const MAX_MILKING_LITRES: f64 = 50.0;
+const MAX_MILK_FAT_PERCENT: f32 = 15.0;
+const MAX_MILK_PROTEIN_PERCENT: f32 = 10.0;
+
+// Length of a standard lactation
+const STANDARD_LACTATION_DAYS: u64 = 305;
+
+// How often the canister timer looks for overdue vaccinations, alerts to escalate and expired event log entries
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
 
 
 // Define the livestock struct 
@@ -12,547 +126,6450 @@ use std::collections::HashMap;
 struct Livestock {
     id: u64,
     breed: String,
-    age: u8,
     height: f32,
-    healthrecords: String,
     healthstatus: HealthStatus,
     medical_records: Vec<Medication>,
     parent_ids: Option<ParentIds>,
     created_at: u64,
     updated_at: Option<u64>,
+    #[serde(default)]
+    vaccinations: Vec<VaccinationRecord>,
+    #[serde(default)]
+    sex: Sex,
+    #[serde(default)]
+    species: Species,
+    #[serde(default)]
+    date_of_birth: u64,
+    #[serde(default)]
+    reproductive_status: ReproductiveStatus,
+    #[serde(default)]
+    disposition: Option<Disposition>,  // How and when the animal left the herd, None while it is in the herd
+    #[serde(default)]
+    birth_weight: Option<f32>,
+    #[serde(default)]
+    breeding_events: Vec<BreedingEvent>,  // Services of a dam and how they turned out
+    #[serde(default)]
+    measurements: Vec<Measurement>,  // Weight, height and body condition history, oldest first
+    #[serde(default)]
+    tags: Vec<String>,  // Free labels, trimmed and lowercase
+    #[serde(default)]
+    status_history: Vec<StatusChange>,  // Health statuses the animal went through, oldest first
+    #[serde(default)]
+    health_records: Vec<HealthRecord>,  // Veterinary examinations, oldest first
+    #[serde(default)]
+    identifiers: Vec<Identifier>,  // External identifiers, retired ones included, oldest first
+    #[serde(default)]
+    location_id: Option<u64>,  // Barn, pen or paddock the animal is kept in
+    #[serde(default)]
+    movements: Vec<Movement>,  // Moves between locations, oldest first
+    #[serde(default)]
+    milk_records: Vec<MilkRecord>,  // Milkings of a dairy animal, oldest first
+}
+
+// Kinds of external identifiers an animal can carry, at most one of each at a time
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+enum IdentifierKind {
+    Rfid,  // Electronic ear tag (EID)
+    VisualTag,
+    NationalId,  // National registration number
+    Microchip,
+}
+
+// An external identifier of an animal. A retired identifier stays with the animal and is never reused.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct Identifier {
+    kind: IdentifierKind,
+    value: String,  // Uppercase, RFID and microchip numbers without spaces
+    assigned_at: u64,
+    assigned_by: Principal,
+    retired_at: Option<u64>,  // None while the animal carries it
+    retired_by: Option<Principal>,
+    retire_reason: String,
+}
+
+impl Identifier {
+    fn is_active(&self) -> bool {
+        self.retired_at.is_none()
+    }
+}
+
+// Kinds of places animals are kept in
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum LocationKind {
+    Barn,
+    Pen,
+    Paddock,
+}
+
+// A barn, pen or paddock. Locations form a hierarchy, e.g. pens inside a barn.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct Location {
+    id: u64,
+    name: String,
+    kind: LocationKind,
+    parent_id: Option<u64>,  // Location this one is part of, None at the top
+    capacity: Option<u32>,  // Most animals it holds, sub-locations included, None for no limit
+}
+
+// Location payload used when adding or editing a location
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct LocationPayload {
+    name: String,
+    kind: LocationKind,
+    parent_id: Option<u64>,
+    capacity: Option<u32>,
+}
+
+// A move of an animal between locations, None standing for no location
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct Movement {
+    animal_id: u64,
+    from: Option<u64>,
+    to: Option<u64>,
+    moved_at: u64,
+    moved_by: Principal,
+}
+
+// Kinds of herd groups
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum GroupKind {
+    Cohort,  // Animals born or bought in the same period
+    Lot,  // Animals sold or finished together
+    PenGroup,  // Animals kept and handled together
+}
+
+// A named group of animals that batch calls can act on
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct HerdGroup {
+    id: u64,
+    name: String,
+    kind: GroupKind,
+    member_ids: Vec<u64>,  // Sorted, without duplicates
+    created_at: u64,
+}
+
+// Animals a batch call acts on
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+enum BatchTarget {
+    Group(u64),  // Members of a group still in the herd
+    Animals(Vec<u64>),
+}
+
+// Outcome of a batch call for one animal, error is None when it succeeded
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct BatchOutcome {
+    animal_id: u64,
+    error: Option<LivestockError>,
+}
+
+// Nutrients of a feed, percentages of dry matter except dry matter itself
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+struct NutrientProfile {
+    dry_matter_percent: f32,
+    crude_protein_percent: f32,
+    crude_fiber_percent: f32,
+    energy_mj_per_kg: f32,  // Metabolizable energy per kg of dry matter
+}
+
+// A feed kept in the inventory
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct FeedItem {
+    id: u64,
+    name: String,
+    nutrients: NutrientProfile,
+    cost_per_kg: f64,
+    stock_kg: f64,
+}
+
+// Feed item payload used when adding or editing a feed
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct FeedItemPayload {
+    name: String,
+    nutrients: NutrientProfile,
+    cost_per_kg: f64,
+}
+
+// A feed in a ration and how much of it each animal gets a day
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct RationComponent {
+    feed_id: u64,
+    kg_per_head_per_day: f64,
+}
+
+// The ration a group is fed over a period. A group follows one plan at a time.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct RationPlan {
+    id: u64,
+    group_id: u64,
+    components: Vec<RationComponent>,
+    start_date: u64,
+    end_date: Option<u64>,  // None while the group is on it
+    set_by: Principal,
+}
+
+// Who a feed delivery went to
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+enum FeedTarget {
+    Group(u64),
+    Animal(u64),
+}
+
+// Feed handed out to a group or an animal on a day
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct FeedDelivery {
+    id: u64,
+    feed_id: u64,
+    quantity_kg: f64,
+    cost: f64,  // Quantity at the price of the feed when it was delivered
+    date: u64,
+    target: FeedTarget,
+    animal_ids: Vec<u64>,  // Animals that shared the feed, the group members in the herd on delivery
+    ration_id: Option<u64>,  // Ration plan the group was on
+    recorded_by: Principal,
+}
+
+// Feed delivery payload used when recording a delivery
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct FeedDeliveryPayload {
+    feed_id: u64,
+    quantity_kg: f64,
+    date: u64,
+    target: FeedTarget,
+}
+
+// Feed eaten by a group, an animal or on a ration over a period against the weight the animals put on
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct FeedReport {
+    head_count: u64,  // Animals that were fed
+    feed_kg: f64,
+    cost: f64,
+    cost_per_head: f64,
+    weighed_head_count: u64,  // Animals weighed twice in the period, the only ones counted in the conversion ratio
+    weight_gain_kg: f64,
+    feed_conversion_ratio: Option<f64>,  // Kg of feed per kg of gain, None without any gain
+}
+
+// Animals in the herd kept in a location and its sub-locations against its capacity
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct LocationOccupancy {
+    location_id: u64,
+    name: String,
+    capacity: Option<u32>,
+    occupancy: u64,
+}
+
+// Ways an animal leaves the herd
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum DispositionKind {
+    Sold,
+    Died,
+    Culled,
+    Transferred,
+}
+
+// How and when an animal left the herd, the record is kept for pedigrees and reporting
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct Disposition {
+    kind: DispositionKind,
+    date: u64,
+    reason: String,
+    buyer: Option<String>,  // Buyer, or the receiving farm of a transfer
+    recorded_by: Principal,
+}
+
+// Disposition payload used when an animal leaves the herd
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct DispositionPayload {
+    kind: DispositionKind,
+    date: u64,
+    reason: String,
+    buyer: Option<String>,
+}
+
+// A veterinary examination of an animal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct HealthRecord {
+    id: u64,
+    animal_id: u64,
+    diagnosis: String,
+    symptoms: Vec<String>,
+    temperature: Option<f32>,  // Degrees Celsius
+    treating_vet: Principal,
+    lab_results: Vec<LabResult>,
+    follow_up_date: Option<u64>,
+    notes: String,
+    recorded_at: u64,
+    recorded_by: Principal,
+    updated_at: Option<u64>,
+}
+
+// Result of a laboratory test attached to a health record
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct LabResult {
+    test_name: String,
+    result: String,
+    reported_at: u64,
+    attachment_url: Option<String>,  // Link to the lab report
+}
+
+// Health record payload used when adding or editing a health record
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct HealthRecordPayload {
+    diagnosis: String,
+    symptoms: Vec<String>,
+    temperature: Option<f32>,
+    treating_vet: Principal,
+    lab_results: Vec<LabResult>,
+    follow_up_date: Option<u64>,
+    notes: String,
+}
+
+// Position after the last health record of a page, passed back for the next page
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct HealthRecordCursor {
+    recorded_at: u64,
+    id: u64,
+}
+
+// One page of find_health_records_by_diagnosis, oldest records first
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct HealthRecordPage {
+    records: Vec<HealthRecord>,
+    next_cursor: Option<HealthRecordCursor>,  // None on the last page
+}
+
+// An animal entering a health status
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct StatusChange {
+    status: HealthStatus,
+    changed_at: u64,
+    changed_by: Principal,
+    note: String,
+}
+
+// A change of health status update_health_status allows
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct HealthTransition {
+    from: HealthStatus,
+    to: HealthStatus,
+}
+
+// Transitions allowed until the owner configures others. A critical animal has to recover before it is healthy again.
+fn default_health_transitions() -> Vec<HealthTransition> {
+    use HealthStatus::*;
+    [
+        (Healthy, Sick),
+        (Healthy, Critical),
+        (Sick, Critical),
+        (Sick, Recovering),
+        (Sick, Healthy),
+        (Critical, Recovering),
+        (Recovering, Healthy),
+        (Recovering, Sick),
+        (Recovering, Critical),
+    ]
+    .into_iter()
+    .map(|(from, to)| HealthTransition { from, to })
+    .collect()
+}
+
+// A timestamped measurement of an animal. Any of the values can be left out.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct Measurement {
+    measured_at: u64,
+    weight: Option<f32>,  // Kilograms
+    height: Option<f32>,
+    body_condition_score: Option<f32>,
+    recorded_by: Option<Principal>,  // None for values carried over from the animal record
+}
+
+// Measurement payload used when recording a measurement
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct MeasurementPayload {
+    measured_at: u64,
+    weight: Option<f32>,
+    height: Option<f32>,
+    body_condition_score: Option<f32>,
+}
+
+// Average daily gain of an animal over a date range next to its breed benchmark
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct GrowthReport {
+    animal_id: u64,
+    breed: String,
+    average_daily_gain: f64,
+    benchmark: f64,
+}
+
+// The yield and milk sample of one milking. The sample values can be left out.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct MilkRecord {
+    id: u64,
+    milked_at: u64,
+    volume_litres: f64,
+    fat_percent: Option<f32>,
+    protein_percent: Option<f32>,
+    somatic_cell_count: Option<u32>,  // Cells per ml
+    withheld: bool,  // Milked while the milk of the animal was under withdrawal
+    recorded_by: Principal,
+}
+
+// Milk record payload used when recording a milking
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct MilkRecordPayload {
+    milked_at: u64,
+    volume_litres: f64,
+    fat_percent: Option<f32>,
+    protein_percent: Option<f32>,
+    somatic_cell_count: Option<u32>,
+}
+
+// Litres an animal gave on a day of its lactation
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct DailyYield {
+    day_in_milk: u64,  // Whole days since calving, the day of calving is day 0
+    litres: f64,
+}
+
+// The milk an animal gave since a calving, up to its next calving
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct LactationCurve {
+    animal_id: u64,
+    calving_date: u64,
+    days_in_milk: u64,  // Up to today while the animal is lactating, otherwise up to its last milking
+    milkings: u64,
+    total_litres: f64,
+    yield_305_days: f64,  // Litres milked in the first 305 days
+    peak_litres: f64,
+    peak_day: Option<u64>,  // Day in milk of the best day, None before the first milking
+    daily_yields: Vec<DailyYield>,
+}
+
+// Milk of the whole herd on a day
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct DailyMilkTotal {
+    day: u64,  // Start of the day (UTC)
+    animals: u64,  // Animals milked
+    milkings: u64,
+    litres: f64,
+    withheld_litres: f64,  // Litres that could not be sold because of a withdrawal
+    fat_percent: Option<f64>,  // Averages weighted by volume over the milkings sampled
+    protein_percent: Option<f64>,
+    somatic_cell_count: Option<f64>,
+}
+
+// Livestock payload used when creating or updating an animal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct LivestockPayload {
+    breed: String,
+    height: f32,
+    sex: Sex,
+    species: Species,
+    date_of_birth: u64,
+}
+
+// Age of an animal in whole days, months and years
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug)]
+struct Age {
+    days: u32,
+    months: u32,
+    years: u32,
+}
+
+// Number of animals in the herd in an age bracket of the age histogram
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct AgeBracket {
+    label: String,
+    min_days: u32,
+    max_days: Option<u32>,  // Exclusive, None for the last bracket
+    count: u64,
+}
+
+// Criteria of list_animals, an animal has to match every one that is set
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, Default)]
+struct AnimalFilter {
+    breed: Option<String>,  // Case insensitive
+    health_status: Option<HealthStatus>,
+    min_age_days: Option<u32>,
+    max_age_days: Option<u32>,
+    sex: Option<Sex>,
+    tags: Vec<String>,  // The animal has to carry all of them
+    include_disposed: Option<bool>,  // Animals that left the herd are left out unless true
+    location_id: Option<u64>,  // Kept in this location or one of its sub-locations
+}
+
+// Order of list_animals, ties are broken by id
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+enum AnimalSortKey {
+    #[default]
+    Id,
+    Age,
+    UpdatedAt,  // Time of the last update, or of the creation if never updated
+}
+
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default)]
+struct AnimalSort {
+    key: AnimalSortKey,
+    descending: bool,
+}
+
+// Position after the last animal of a page, passed back to list_animals for the next page
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct AnimalCursor {
+    sort_value: u64,
+    id: u64,
+}
+
+// One page of list_animals
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct AnimalPage {
+    animals: Vec<Livestock>,
+    next_cursor: Option<AnimalCursor>,  // None on the last page
+    total: u64,  // Number of animals matching the filter across all pages
+}
+
+// Sex of the animal, Unknown for records created before it was tracked
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
+enum Sex {
+    Male,
+    Female,
+    #[default]
+    Unknown,
+}
+
+// Species of the animal. The farm started out with cattle only, so that is the default.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, Hash)]
+enum Species {
+    #[default]
+    Cattle,
+    Sheep,
+    Goat,
+    Pig,
+    Horse,
+}
+
+// How a dam was served
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+enum ServiceMethod {
+    Natural,
+    ArtificialInsemination,
+}
+
+// Where a breeding event stands between service and birth
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+enum BreedingStatus {
+    Served,       // Waiting for a pregnancy check
+    Pregnant,     // Pregnancy confirmed
+    NotPregnant,  // Pregnancy check came back negative
+    Delivered,    // Offspring born and recorded
+}
+
+// A service of a dam, from mating or insemination through to birth
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct BreedingEvent {
+    id: u64,
+    sire_id: u64,
+    method: ServiceMethod,
+    service_date: u64,
+    expected_due_date: u64,
+    status: BreedingStatus,
+    pregnancy_checked_at: Option<u64>,
+    birth_date: Option<u64>,
+    offspring_ids: Vec<u64>,
+}
+
+// An offspring recorded at birth
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct OffspringPayload {
+    sex: Sex,
+    birth_weight: f32,
+    breed: Option<String>,  // Defaults to the breed of the dam
+}
+
+// A birth expected from a served or pregnant dam
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct ExpectedBirth {
+    dam_id: u64,
+    sire_id: u64,
+    breeding_event_id: u64,
+    status: BreedingStatus,
+    expected_due_date: u64,
+}
+
+// Reproductive state of a female animal
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+enum ReproductiveStatus {
+    #[default]
+    Open,
+    InHeat { observed_at: u64 },
+    Pregnant { expected_due_date: u64 },
+    Lactating { since: u64 },
+}
+
+// Vacination and medication tracking
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct Medication {
+    id: u64,
+    name: String,
+    dosage: String,
+    start_date: u64,
+    end_date: u64,
+    #[serde(default)]
+    animal_id: u64,
+    #[serde(default)]
+    frequency_hours: u32,  // Hours between doses, 0 for a single dose
+    #[serde(default)]
+    route: AdministrationRoute,
+    #[serde(default)]
+    prescribed_by: Option<Principal>,
+    #[serde(default)]
+    doses: Vec<DoseRecord>,
+    #[serde(default)]
+    status: MedicationStatus,
+}
+
+// Medication payload used when scheduling or editing a medication
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct MedicationPayload {
+    name: String,
+    dosage: String,
+    start_date: u64,
+    end_date: u64,
+    frequency_hours: u32,
+    route: AdministrationRoute,
+    prescribed_by: Principal,
+}
+
+// A single dose of a medication given to an animal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct DoseRecord {
+    administered_at: u64,
+    administered_by: Principal,
+}
+
+// How a medication is given to the animal
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+enum AdministrationRoute {
+    Oral,
+    Intramuscular,
+    Subcutaneous,
+    Intravenous,
+    Topical,
+    Intramammary,
+    #[default]
+    Other,
+}
+
+// Lifecycle of a medication entry
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+enum MedicationStatus {
+    #[default]
+    Active,
+    Completed,
+    Cancelled,
+}
+
+// Days the meat and milk of a treated animal are held back after the last dose of a medication product
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+struct WithdrawalPeriod {
+    meat_days: u32,
+    milk_days: u32,
+}
+
+// When the meat and milk of an animal are safe again, None when no withdrawal ever applied
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct Withdrawal {
+    animal_id: u64,
+    meat_safe_after: Option<u64>,
+    milk_safe_after: Option<u64>,
+}
+
+impl Withdrawal {
+    fn meat_withheld(&self, at: u64) -> bool {
+        matches!(self.meat_safe_after, Some(safe_after) if safe_after > at)
+    }
+
+    fn milk_withheld(&self, at: u64) -> bool {
+        matches!(self.milk_safe_after, Some(safe_after) if safe_after > at)
+    }
+}
+
+// Vaccine protocol describing the doses and boosters an animal needs
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct VaccineProtocol {
+    id: u64,
+    vaccine_name: String,
+    breed: Option<String>,  // Breed the protocol applies to, None for any breed
+    #[serde(default)]
+    species: Option<Species>,  // Species the protocol applies to, None for any species
+    first_dose_age_days: u32,  // Age at which the first dose is due
+    doses_required: u32,  // Doses in the primary course
+    dose_interval_days: u32,  // Days between doses of the primary course
+    booster_interval_days: Option<u32>,  // Days between boosters once the primary course is done
+}
+
+// Vaccine protocol payload used when adding a protocol
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct VaccineProtocolPayload {
+    vaccine_name: String,
+    breed: Option<String>,
+    species: Option<Species>,
+    first_dose_age_days: u32,
+    doses_required: u32,
+    dose_interval_days: u32,
+    booster_interval_days: Option<u32>,
+}
+
+// A vaccine dose given to an animal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct VaccinationRecord {
+    protocol_id: u64,
+    dose_number: u32,
+    administered_at: u64,
+    administered_by: Principal,
+}
+
+// The next vaccine dose an animal is due for under a protocol
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct DueVaccination {
+    animal_id: u64,
+    protocol_id: u64,
+    vaccine_name: String,
+    dose_number: u32,
+    booster: bool,
+    due_date: u64,
+    overdue: bool,
+}
+
+// Parent IDs struct of the animal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct ParentIds {
+    parent1_id: u64,
+    parent2_id: u64,
+}
+
+// Who an animal is and how it is doing, used where whole records would make a response too large
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+struct AnimalSummary {
+    id: u64,
+    breed: String,
+    sex: Sex,
+    species: Species,
+    health_status: HealthStatus,
+    in_herd: bool,
+}
+
+// Node of a pedigree tree. An ancestor reached through more than one line is only
+// expanded the first time, later occurrences are marked as repeated and carry no parents.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct PedigreeNode {
+    animal: AnimalSummary,
+    generation: u32,
+    repeated: bool,
+    parents: Vec<PedigreeNode>,
+}
+
+// An offspring of an animal and how many generations below it sits
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct Descendant {
+    animal: AnimalSummary,
+    generation: u32,
+}
+
+// What record_service does with a pairing above the inbreeding threshold
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq)]
+enum InbreedingAction {
+    #[default]
+    Refuse,
+    Warn,
+}
+
+// Highest inbreeding coefficient allowed for the offspring of a pairing
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+struct InbreedingPolicy {
+    threshold: f64,
+    action: InbreedingAction,
+}
+
+impl Default for InbreedingPolicy {
+    fn default() -> Self {
+        // 6.25% is the coefficient of the offspring of first cousins
+        InbreedingPolicy {
+            threshold: 0.0625,
+            action: InbreedingAction::Refuse,
+        }
+    }
+}
+
+// A candidate mate and the inbreeding coefficient of their offspring
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct MateSuggestion {
+    animal_id: u64,
+    breed: String,
+    inbreeding_coefficient: f64,
+}
+
+// Health alert struct
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct HealthAlert {
+    #[serde(default)]
+    id: u64,
+    animal_id: u64,
+    kind: AlertKind,
+    status: HealthStatus,  // Health status of the animal when the alert was raised
+    timestamp: u64,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    severity: AlertSeverity,
+    #[serde(default)]
+    acknowledged_by: Option<Principal>,
+    #[serde(default)]
+    acknowledged_at: Option<u64>,
+    #[serde(default)]
+    resolved_by: Option<Principal>,  // None for alerts resolved by the canister
+    #[serde(default)]
+    resolved_at: Option<u64>,
+    #[serde(default)]
+    escalated_at: Option<u64>,  // Last time the timer raised the severity
+}
+
+impl HealthAlert {
+    fn is_open(&self) -> bool {
+        self.resolved_at.is_none()
+    }
+}
+
+// One page of get_health_alerts, oldest alerts first
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct AlertPage {
+    alerts: Vec<HealthAlert>,
+    next_cursor: Option<u64>,  // Id of the last alert returned, None on the last page
+}
+
+// What raised an alert
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum AlertKind {
+    StatusChange(HealthStatus),  // The health status the animal changed to
+    Mastitis,
+    VaccinationOverdue,
+}
+
+// How urgent an alert is, from least to most
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+enum AlertSeverity {
+    Low,
+    #[default]
+    Medium,
+    High,
+    Critical,
+}
+
+impl AlertSeverity {
+    // The next level up, None once Critical
+    fn escalated(self) -> Option<AlertSeverity> {
+        match self {
+            AlertSeverity::Low => Some(AlertSeverity::Medium),
+            AlertSeverity::Medium => Some(AlertSeverity::High),
+            AlertSeverity::High => Some(AlertSeverity::Critical),
+            AlertSeverity::Critical => None,
+        }
+    }
+}
+
+// Event logging struct
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct EventLog {
+    id: u64,
+    timestamp: u64,
+    actor: Principal,  // Principal that made the change
+    animal_id: Option<u64>,  // Animal the event is about, if any
+    event: LivestockEvent,
+    #[serde(default)]
+    changes: Vec<FieldChange>,  // Fields the event changed
+}
+
+// A field changed by an event, with its values rendered as text. None when the field was not set before or is gone after.
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct FieldChange {
+    field: String,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+// What happened, with the values before and after the change where there are any
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+enum LivestockEvent {
+    RoleGranted { principal: Principal, role: Role },
+    RoleRevoked { principal: Principal, role: Role },
+    AnimalCreated { breed: String, species: Species, sex: Sex, date_of_birth: u64 },
+    AnimalUpdated { before: LivestockPayload, after: LivestockPayload },
+    AnimalDeleted,
+    TagsUpdated { before: Vec<String>, after: Vec<String> },
+    IdentifierAdded { identifier: Identifier },
+    AnimalRetagged { before: Identifier, after: Identifier },
+    AnimalMoved { from: Option<u64>, to: Option<u64> },
+    LocationAdded { location: Location },
+    LocationUpdated { before: Location, after: Location },
+    LocationRemoved { location: Location },
+    GroupCreated { group: HerdGroup },
+    GroupUpdated { before: HerdGroup, after: HerdGroup },
+    GroupDeleted { group: HerdGroup },
+    FeedItemAdded { item: FeedItem },
+    FeedItemUpdated { before: FeedItem, after: FeedItem },
+    FeedRestocked { feed_id: u64, before_kg: f64, after_kg: f64 },
+    RationPlanStarted { plan: RationPlan },
+    RationPlanEnded { plan_id: u64, group_id: u64 },
+    FeedDelivered { delivery: FeedDelivery },
+    ReproductiveStatusUpdated { before: ReproductiveStatus, after: ReproductiveStatus },
+    AnimalDisposed { disposition: Disposition },
+    MilkSold { litres: f64, buyer: String },
+    HealthStatusUpdated {
+        before: HealthStatus,
+        after: HealthStatus,
+        #[serde(default)]
+        note: String,
+    },
+    HealthTransitionsChanged { before: Vec<HealthTransition>, after: Vec<HealthTransition> },
+    HealthRecordAdded { record: HealthRecord },
+    HealthRecordUpdated { before: HealthRecord, after: HealthRecord },
+    HealthRecordDeleted { record: HealthRecord },
+    MedicationScheduled { medication: Medication },
+    MedicationUpdated { before: Medication, after: Medication },
+    MedicationCancelled { medication_id: u64 },
+    MedicationDoseRecorded { medication_id: u64, dose: DoseRecord, status: MedicationStatus },
+    WithdrawalPeriodUpdated { product: String, before: Option<WithdrawalPeriod>, after: Option<WithdrawalPeriod> },
+    VaccineProtocolAdded { protocol: VaccineProtocol },
+    VaccineProtocolRemoved { protocol: VaccineProtocol },
+    VaccinationRecorded { record: VaccinationRecord },
+    AlertAcknowledged { alert_id: u64 },
+    AlertResolved { alert_id: u64 },
+    AlertEscalated { alert_id: u64, before: AlertSeverity, after: AlertSeverity },
+    AlertEscalationWindowChanged { before: u32, after: u32 },
+    AnimalServed { breeding_event_id: u64, sire_id: u64, method: ServiceMethod, expected_due_date: u64 },
+    InbreedingWarning { sire_id: u64, inbreeding_coefficient: f64, threshold: f64 },
+    PregnancyChecked { breeding_event_id: u64, pregnant: bool },
+    BirthRecorded { breeding_event_id: u64, sire_id: u64, offspring_ids: Vec<u64> },
+    ParentsSet { before: Option<ParentIds>, after: ParentIds },
+    InbreedingPolicyChanged { before: InbreedingPolicy, after: InbreedingPolicy },
+    MeasurementRecorded { measurement: Measurement },
+    MilkingRecorded { record: MilkRecord },
+    MastitisThresholdChanged { before: u32, after: u32 },
+    GrowthBenchmarkUpdated { breed: String, before: Option<f64>, after: Option<f64> },
+    EventRetentionChanged { before: EventRetention, after: EventRetention },
+    EventsCompacted { removed: u64, older_than: u64 },
+}
+
+// Kinds of LivestockEvent, used to filter the event log
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+enum EventKind {
+    RoleGranted,
+    RoleRevoked,
+    AnimalCreated,
+    AnimalUpdated,
+    AnimalDeleted,
+    TagsUpdated,
+    IdentifierAdded,
+    AnimalRetagged,
+    AnimalMoved,
+    LocationAdded,
+    LocationUpdated,
+    LocationRemoved,
+    GroupCreated,
+    GroupUpdated,
+    GroupDeleted,
+    FeedItemAdded,
+    FeedItemUpdated,
+    FeedRestocked,
+    RationPlanStarted,
+    RationPlanEnded,
+    FeedDelivered,
+    ReproductiveStatusUpdated,
+    AnimalDisposed,
+    MilkSold,
+    HealthStatusUpdated,
+    HealthTransitionsChanged,
+    HealthRecordAdded,
+    HealthRecordUpdated,
+    HealthRecordDeleted,
+    MedicationScheduled,
+    MedicationUpdated,
+    MedicationCancelled,
+    MedicationDoseRecorded,
+    WithdrawalPeriodUpdated,
+    VaccineProtocolAdded,
+    VaccineProtocolRemoved,
+    VaccinationRecorded,
+    AlertAcknowledged,
+    AlertResolved,
+    AlertEscalated,
+    AlertEscalationWindowChanged,
+    AnimalServed,
+    InbreedingWarning,
+    PregnancyChecked,
+    BirthRecorded,
+    ParentsSet,
+    InbreedingPolicyChanged,
+    MeasurementRecorded,
+    MilkingRecorded,
+    MastitisThresholdChanged,
+    GrowthBenchmarkUpdated,
+    EventRetentionChanged,
+    EventsCompacted,
+}
+
+impl LivestockEvent {
+    fn kind(&self) -> EventKind {
+        match self {
+            LivestockEvent::RoleGranted { .. } => EventKind::RoleGranted,
+            LivestockEvent::RoleRevoked { .. } => EventKind::RoleRevoked,
+            LivestockEvent::AnimalCreated { .. } => EventKind::AnimalCreated,
+            LivestockEvent::AnimalUpdated { .. } => EventKind::AnimalUpdated,
+            LivestockEvent::AnimalDeleted => EventKind::AnimalDeleted,
+            LivestockEvent::TagsUpdated { .. } => EventKind::TagsUpdated,
+            LivestockEvent::IdentifierAdded { .. } => EventKind::IdentifierAdded,
+            LivestockEvent::AnimalRetagged { .. } => EventKind::AnimalRetagged,
+            LivestockEvent::AnimalMoved { .. } => EventKind::AnimalMoved,
+            LivestockEvent::LocationAdded { .. } => EventKind::LocationAdded,
+            LivestockEvent::LocationUpdated { .. } => EventKind::LocationUpdated,
+            LivestockEvent::LocationRemoved { .. } => EventKind::LocationRemoved,
+            LivestockEvent::GroupCreated { .. } => EventKind::GroupCreated,
+            LivestockEvent::GroupUpdated { .. } => EventKind::GroupUpdated,
+            LivestockEvent::GroupDeleted { .. } => EventKind::GroupDeleted,
+            LivestockEvent::FeedItemAdded { .. } => EventKind::FeedItemAdded,
+            LivestockEvent::FeedItemUpdated { .. } => EventKind::FeedItemUpdated,
+            LivestockEvent::FeedRestocked { .. } => EventKind::FeedRestocked,
+            LivestockEvent::RationPlanStarted { .. } => EventKind::RationPlanStarted,
+            LivestockEvent::RationPlanEnded { .. } => EventKind::RationPlanEnded,
+            LivestockEvent::FeedDelivered { .. } => EventKind::FeedDelivered,
+            LivestockEvent::ReproductiveStatusUpdated { .. } => EventKind::ReproductiveStatusUpdated,
+            LivestockEvent::AnimalDisposed { .. } => EventKind::AnimalDisposed,
+            LivestockEvent::MilkSold { .. } => EventKind::MilkSold,
+            LivestockEvent::HealthStatusUpdated { .. } => EventKind::HealthStatusUpdated,
+            LivestockEvent::HealthTransitionsChanged { .. } => EventKind::HealthTransitionsChanged,
+            LivestockEvent::HealthRecordAdded { .. } => EventKind::HealthRecordAdded,
+            LivestockEvent::HealthRecordUpdated { .. } => EventKind::HealthRecordUpdated,
+            LivestockEvent::HealthRecordDeleted { .. } => EventKind::HealthRecordDeleted,
+            LivestockEvent::MedicationScheduled { .. } => EventKind::MedicationScheduled,
+            LivestockEvent::MedicationUpdated { .. } => EventKind::MedicationUpdated,
+            LivestockEvent::MedicationCancelled { .. } => EventKind::MedicationCancelled,
+            LivestockEvent::MedicationDoseRecorded { .. } => EventKind::MedicationDoseRecorded,
+            LivestockEvent::WithdrawalPeriodUpdated { .. } => EventKind::WithdrawalPeriodUpdated,
+            LivestockEvent::VaccineProtocolAdded { .. } => EventKind::VaccineProtocolAdded,
+            LivestockEvent::VaccineProtocolRemoved { .. } => EventKind::VaccineProtocolRemoved,
+            LivestockEvent::VaccinationRecorded { .. } => EventKind::VaccinationRecorded,
+            LivestockEvent::AlertAcknowledged { .. } => EventKind::AlertAcknowledged,
+            LivestockEvent::AlertResolved { .. } => EventKind::AlertResolved,
+            LivestockEvent::AlertEscalated { .. } => EventKind::AlertEscalated,
+            LivestockEvent::AlertEscalationWindowChanged { .. } => EventKind::AlertEscalationWindowChanged,
+            LivestockEvent::AnimalServed { .. } => EventKind::AnimalServed,
+            LivestockEvent::InbreedingWarning { .. } => EventKind::InbreedingWarning,
+            LivestockEvent::PregnancyChecked { .. } => EventKind::PregnancyChecked,
+            LivestockEvent::BirthRecorded { .. } => EventKind::BirthRecorded,
+            LivestockEvent::ParentsSet { .. } => EventKind::ParentsSet,
+            LivestockEvent::InbreedingPolicyChanged { .. } => EventKind::InbreedingPolicyChanged,
+            LivestockEvent::MeasurementRecorded { .. } => EventKind::MeasurementRecorded,
+            LivestockEvent::MilkingRecorded { .. } => EventKind::MilkingRecorded,
+            LivestockEvent::MastitisThresholdChanged { .. } => EventKind::MastitisThresholdChanged,
+            LivestockEvent::GrowthBenchmarkUpdated { .. } => EventKind::GrowthBenchmarkUpdated,
+            LivestockEvent::EventRetentionChanged { .. } => EventKind::EventRetentionChanged,
+            LivestockEvent::EventsCompacted { .. } => EventKind::EventsCompacted,
+        }
+    }
+
+    // The fields the event changed. Records added or removed as a whole show up as a single field.
+    fn changes(&self) -> Vec<FieldChange> {
+        let mut changes = Vec::new();
+        match self {
+            LivestockEvent::RoleGranted { principal, role } => {
+                field_change(&mut changes, &format!("roles[{}]", principal), None, Some(role));
+            }
+            LivestockEvent::RoleRevoked { principal, role } => {
+                field_change(&mut changes, &format!("roles[{}]", principal), Some(role), None);
+            }
+            LivestockEvent::AnimalCreated { breed, species, sex, date_of_birth } => {
+                field_change(&mut changes, "breed", None, Some(breed));
+                field_change(&mut changes, "species", None, Some(species));
+                field_change(&mut changes, "sex", None, Some(sex));
+                field_change(&mut changes, "date_of_birth", None, Some(date_of_birth));
+            }
+            LivestockEvent::AnimalUpdated { before, after } => {
+                field_change(&mut changes, "breed", Some(&before.breed), Some(&after.breed));
+                field_change(&mut changes, "height", Some(&before.height), Some(&after.height));
+                field_change(&mut changes, "sex", Some(&before.sex), Some(&after.sex));
+                field_change(&mut changes, "species", Some(&before.species), Some(&after.species));
+                field_change(&mut changes, "date_of_birth", Some(&before.date_of_birth), Some(&after.date_of_birth));
+            }
+            LivestockEvent::TagsUpdated { before, after } => {
+                field_change(&mut changes, "tags", Some(before), Some(after));
+            }
+            LivestockEvent::IdentifierAdded { identifier } => {
+                field_change(&mut changes, &format!("identifiers[{:?}]", identifier.kind), None, Some(&identifier.value));
+            }
+            LivestockEvent::AnimalRetagged { before, after } => {
+                field_change(&mut changes, &format!("identifiers[{:?}]", after.kind), Some(&before.value), Some(&after.value));
+            }
+            LivestockEvent::AnimalMoved { from, to } => {
+                field_change(&mut changes, "location_id", Some(from), Some(to));
+            }
+            LivestockEvent::LocationAdded { location } => {
+                field_change(&mut changes, &format!("locations[{}]", location.id), None, Some(&location.name));
+            }
+            LivestockEvent::LocationUpdated { before, after } => {
+                let field = |name: &str| format!("locations[{}].{}", after.id, name);
+                field_change(&mut changes, &field("name"), Some(&before.name), Some(&after.name));
+                field_change(&mut changes, &field("kind"), Some(&before.kind), Some(&after.kind));
+                field_change(&mut changes, &field("parent_id"), Some(&before.parent_id), Some(&after.parent_id));
+                field_change(&mut changes, &field("capacity"), Some(&before.capacity), Some(&after.capacity));
+            }
+            LivestockEvent::LocationRemoved { location } => {
+                field_change(&mut changes, &format!("locations[{}]", location.id), Some(&location.name), None);
+            }
+            LivestockEvent::GroupCreated { group } => {
+                field_change(&mut changes, &format!("groups[{}]", group.id), None, Some(&group.name));
+            }
+            LivestockEvent::GroupUpdated { before, after } => {
+                let field = |name: &str| format!("groups[{}].{}", after.id, name);
+                field_change(&mut changes, &field("name"), Some(&before.name), Some(&after.name));
+                field_change(&mut changes, &field("kind"), Some(&before.kind), Some(&after.kind));
+                field_change(&mut changes, &field("member_ids"), Some(&before.member_ids), Some(&after.member_ids));
+            }
+            LivestockEvent::GroupDeleted { group } => {
+                field_change(&mut changes, &format!("groups[{}]", group.id), Some(&group.name), None);
+            }
+            LivestockEvent::FeedItemAdded { item } => {
+                field_change(&mut changes, &format!("feed_items[{}]", item.id), None, Some(&item.name));
+            }
+            LivestockEvent::FeedItemUpdated { before, after } => {
+                let field = |name: &str| format!("feed_items[{}].{}", after.id, name);
+                field_change(&mut changes, &field("name"), Some(&before.name), Some(&after.name));
+                field_change(&mut changes, &field("nutrients"), Some(&before.nutrients), Some(&after.nutrients));
+                field_change(&mut changes, &field("cost_per_kg"), Some(&before.cost_per_kg), Some(&after.cost_per_kg));
+            }
+            LivestockEvent::FeedRestocked { feed_id, before_kg, after_kg } => {
+                field_change(&mut changes, &format!("feed_items[{}].stock_kg", feed_id), Some(before_kg), Some(after_kg));
+            }
+            LivestockEvent::RationPlanStarted { plan } => {
+                field_change(&mut changes, &format!("ration_plans[{}]", plan.id), None, Some(&plan.components));
+            }
+            LivestockEvent::RationPlanEnded { plan_id, .. } => {
+                field_change(&mut changes, &format!("ration_plans[{}].ended", plan_id), Some(&false), Some(&true));
+            }
+            LivestockEvent::FeedDelivered { delivery } => {
+                field_change(&mut changes, &format!("feed_deliveries[{}]", delivery.id), None, Some(&delivery.quantity_kg));
+            }
+            LivestockEvent::ReproductiveStatusUpdated { before, after } => {
+                field_change(&mut changes, "reproductive_status", Some(before), Some(after));
+            }
+            LivestockEvent::AnimalDisposed { disposition } => {
+                field_change(&mut changes, "disposition", None, Some(disposition));
+            }
+            LivestockEvent::HealthStatusUpdated { before, after, .. } => {
+                field_change(&mut changes, "healthstatus", Some(before), Some(after));
+            }
+            LivestockEvent::HealthTransitionsChanged { before, after } => {
+                field_change(&mut changes, "health_transitions", Some(before), Some(after));
+            }
+            LivestockEvent::HealthRecordAdded { record } => {
+                field_change(&mut changes, &format!("health_records[{}]", record.id), None, Some(&record.diagnosis));
+            }
+            LivestockEvent::HealthRecordUpdated { before, after } => {
+                let field = |name: &str| format!("health_records[{}].{}", after.id, name);
+                field_change(&mut changes, &field("diagnosis"), Some(&before.diagnosis), Some(&after.diagnosis));
+                field_change(&mut changes, &field("symptoms"), Some(&before.symptoms), Some(&after.symptoms));
+                field_change(&mut changes, &field("temperature"), Some(&before.temperature), Some(&after.temperature));
+                field_change(&mut changes, &field("treating_vet"), Some(&before.treating_vet), Some(&after.treating_vet));
+                field_change(&mut changes, &field("lab_results"), Some(&before.lab_results), Some(&after.lab_results));
+                field_change(&mut changes, &field("follow_up_date"), Some(&before.follow_up_date), Some(&after.follow_up_date));
+                field_change(&mut changes, &field("notes"), Some(&before.notes), Some(&after.notes));
+            }
+            LivestockEvent::HealthRecordDeleted { record } => {
+                field_change(&mut changes, &format!("health_records[{}]", record.id), Some(&record.diagnosis), None);
+            }
+            LivestockEvent::MedicationScheduled { medication } => {
+                field_change(&mut changes, &format!("medical_records[{}]", medication.id), None, Some(&medication.name));
+            }
+            LivestockEvent::MedicationUpdated { before, after } => {
+                let field = |name: &str| format!("medical_records[{}].{}", after.id, name);
+                field_change(&mut changes, &field("name"), Some(&before.name), Some(&after.name));
+                field_change(&mut changes, &field("dosage"), Some(&before.dosage), Some(&after.dosage));
+                field_change(&mut changes, &field("start_date"), Some(&before.start_date), Some(&after.start_date));
+                field_change(&mut changes, &field("end_date"), Some(&before.end_date), Some(&after.end_date));
+                field_change(&mut changes, &field("frequency_hours"), Some(&before.frequency_hours), Some(&after.frequency_hours));
+                field_change(&mut changes, &field("route"), Some(&before.route), Some(&after.route));
+                field_change(&mut changes, &field("prescribed_by"), Some(&before.prescribed_by), Some(&after.prescribed_by));
+                field_change(&mut changes, &field("status"), Some(&before.status), Some(&after.status));
+            }
+            LivestockEvent::MedicationCancelled { medication_id } => {
+                let field = format!("medical_records[{}].status", medication_id);
+                field_change(&mut changes, &field, Some(&MedicationStatus::Active), Some(&MedicationStatus::Cancelled));
+            }
+            LivestockEvent::MedicationDoseRecorded { medication_id, dose, status } => {
+                field_change(&mut changes, &format!("medical_records[{}].doses", medication_id), None, Some(&dose.administered_at));
+                let field = format!("medical_records[{}].status", medication_id);
+                field_change(&mut changes, &field, Some(&MedicationStatus::Active), Some(status));
+            }
+            LivestockEvent::VaccineProtocolAdded { protocol } => {
+                field_change(&mut changes, &format!("vaccine_protocols[{}]", protocol.id), None, Some(&protocol.vaccine_name));
+            }
+            LivestockEvent::VaccineProtocolRemoved { protocol } => {
+                field_change(&mut changes, &format!("vaccine_protocols[{}]", protocol.id), Some(&protocol.vaccine_name), None);
+            }
+            LivestockEvent::VaccinationRecorded { record } => {
+                let field = format!("vaccinations[{}]", record.protocol_id);
+                field_change(&mut changes, &field, None, Some(&record.dose_number));
+            }
+            LivestockEvent::AnimalServed { breeding_event_id, expected_due_date, .. } => {
+                field_change(&mut changes, &format!("breeding_events[{}]", breeding_event_id), None, Some(expected_due_date));
+            }
+            LivestockEvent::PregnancyChecked { breeding_event_id, pregnant } => {
+                field_change(&mut changes, &format!("breeding_events[{}].pregnant", breeding_event_id), None, Some(pregnant));
+            }
+            LivestockEvent::BirthRecorded { breeding_event_id, offspring_ids, .. } => {
+                field_change(&mut changes, &format!("breeding_events[{}].offspring_ids", breeding_event_id), None, Some(offspring_ids));
+            }
+            LivestockEvent::ParentsSet { before, after } => {
+                field_change(&mut changes, "parent_ids", before.as_ref(), Some(after));
+            }
+            LivestockEvent::InbreedingPolicyChanged { before, after } => {
+                field_change(&mut changes, "inbreeding_policy", Some(before), Some(after));
+            }
+            LivestockEvent::MeasurementRecorded { measurement } => {
+                field_change(&mut changes, "measurements", None, Some(measurement));
+            }
+            LivestockEvent::MilkingRecorded { record } => {
+                field_change(&mut changes, "milk_records", None, Some(record));
+            }
+            LivestockEvent::MastitisThresholdChanged { before, after } => {
+                field_change(&mut changes, "mastitis_cell_count", Some(before), Some(after));
+            }
+            LivestockEvent::WithdrawalPeriodUpdated { product, before, after } => {
+                field_change(&mut changes, &format!("withdrawal_periods[{}]", product), before.as_ref(), after.as_ref());
+            }
+            LivestockEvent::GrowthBenchmarkUpdated { breed, before, after } => {
+                field_change(&mut changes, &format!("growth_benchmarks[{}]", breed), before.as_ref(), after.as_ref());
+            }
+            LivestockEvent::EventRetentionChanged { before, after } => {
+                field_change(&mut changes, "event_retention", Some(before), Some(after));
+            }
+            LivestockEvent::AlertAcknowledged { alert_id } => {
+                field_change(&mut changes, &format!("health_alerts[{}].acknowledged", alert_id), Some(&false), Some(&true));
+            }
+            LivestockEvent::AlertResolved { alert_id } => {
+                field_change(&mut changes, &format!("health_alerts[{}].resolved", alert_id), Some(&false), Some(&true));
+            }
+            LivestockEvent::AlertEscalated { alert_id, before, after } => {
+                field_change(&mut changes, &format!("health_alerts[{}].severity", alert_id), Some(before), Some(after));
+            }
+            LivestockEvent::AlertEscalationWindowChanged { before, after } => {
+                field_change(&mut changes, "alert_escalation_hours", Some(before), Some(after));
+            }
+            LivestockEvent::AnimalDeleted
+            | LivestockEvent::InbreedingWarning { .. }
+            | LivestockEvent::MilkSold { .. }
+            | LivestockEvent::EventsCompacted { .. } => {}
+        }
+        changes
+    }
+}
+
+// Adds a field to the changes when its value differs before and after
+fn field_change<T: PartialEq + fmt::Debug + ?Sized>(changes: &mut Vec<FieldChange>, field: &str, before: Option<&T>, after: Option<&T>) {
+    if before != after {
+        changes.push(FieldChange {
+            field: field.to_string(),
+            before: before.map(|value| format!("{:?}", value)),
+            after: after.map(|value| format!("{:?}", value)),
+        });
+    }
+}
+
+// Criteria of get_event_logs, an entry has to match every one that is set
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, Default)]
+struct EventQuery {
+    animal_id: Option<u64>,
+    kind: Option<EventKind>,
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+// One page of get_event_logs, newest entries first
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
+struct EventPage {
+    events: Vec<EventLog>,
+    next_cursor: Option<u64>,  // Id of the last entry returned, None on the last page
+}
+
+// How much of the event log is kept. The oldest entries are dropped first.
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct EventRetention {
+    max_entries: u64,
+    max_age_days: Option<u32>,  // Entries older than this are dropped by the hourly timer
+}
+
+impl Default for EventRetention {
+    fn default() -> Self {
+        EventRetention {
+            max_entries: DEFAULT_EVENT_LOG_ENTRIES,
+            max_age_days: None,
+        }
+    }
+}
+
+impl Medication {
+    // Number of doses the course needs between its start and end dates
+    fn expected_doses(&self) -> u64 {
+        if self.frequency_hours == 0 {
+            return 1;
+        }
+        (self.end_date - self.start_date) / (self.frequency_hours as u64 * NANOS_PER_HOUR) + 1
+    }
+
+    // When the next dose is due, None once every dose was given
+    fn next_dose_due(&self) -> Option<u64> {
+        let given = self.doses.len() as u64;
+        if given >= self.expected_doses() {
+            return None;
+        }
+        Some(self.start_date + given * self.frequency_hours as u64 * NANOS_PER_HOUR)
+    }
+
+    // An active course that has started
+    fn is_active(&self, now: u64) -> bool {
+        self.status == MedicationStatus::Active && self.start_date <= now
+    }
+
+    // An active course whose next dose is past due
+    fn is_overdue(&self, now: u64) -> bool {
+        self.status == MedicationStatus::Active && self.next_dose_due().is_some_and(|due| due < now)
+    }
+}
+
+impl VaccineProtocol {
+    // Whether the protocol covers the animal's breed and species
+    fn applies_to(&self, animal: &Livestock) -> bool {
+        let breed_matches = match &self.breed {
+            Some(breed) => breed.eq_ignore_ascii_case(&animal.breed),
+            None => true,
+        };
+        let species_matches = match self.species {
+            Some(species) => species == animal.species,
+            None => true,
+        };
+        breed_matches && species_matches
+    }
+
+    // The next dose the animal needs under this protocol, None once the course is done and no booster follows
+    fn next_due(&self, animal: &Livestock, now: u64) -> Option<DueVaccination> {
+        let given: Vec<&VaccinationRecord> = animal.vaccinations.iter().filter(|record| record.protocol_id == self.id).collect();
+        let dose_number = given.len() as u32 + 1;
+        let due_date = match given.last() {
+            None => animal.date_of_birth + self.first_dose_age_days as u64 * NANOS_PER_DAY,
+            Some(last) if dose_number <= self.doses_required => last.administered_at + self.dose_interval_days as u64 * NANOS_PER_DAY,
+            Some(last) => last.administered_at + self.booster_interval_days? as u64 * NANOS_PER_DAY,
+        };
+
+        Some(DueVaccination {
+            animal_id: animal.id,
+            protocol_id: self.id,
+            vaccine_name: self.vaccine_name.clone(),
+            dose_number,
+            booster: dose_number > self.doses_required,
+            due_date,
+            overdue: due_date < now,
+        })
+    }
+}
+
+impl Species {
+    // Average gestation length of the species in days
+    fn gestation_days(&self) -> u64 {
+        match self {
+            Species::Cattle => 283,
+            Species::Sheep => 147,
+            Species::Goat => 150,
+            Species::Pig => 114,
+            Species::Horse => 340,
+        }
+    }
+}
+
+impl Livestock {
+    // A new, healthy animal with validated details, created by the caller at the given time
+    fn new(caller: Principal, id: u64, payload: LivestockPayload, now: u64) -> Livestock {
+        let LivestockPayload { breed, height, sex, species, date_of_birth } = payload;
+
+        let mut animal = Livestock {
+            id,
+            breed,
+            height,
+            healthstatus: HealthStatus::Healthy,
+            medical_records: Vec::new(),
+            parent_ids: None,
+            created_at: now,
+            updated_at: None,
+            vaccinations: Vec::new(),
+            sex,
+            species,
+            date_of_birth,
+            reproductive_status: ReproductiveStatus::Open,
+            disposition: None,
+            birth_weight: None,
+            breeding_events: Vec::new(),
+            measurements: Vec::new(),
+            tags: Vec::new(),
+            status_history: vec![StatusChange {
+                status: HealthStatus::Healthy,
+                changed_at: now,
+                changed_by: caller,
+                note: String::new(),
+            }],
+            health_records: Vec::new(),
+            identifiers: Vec::new(),
+            location_id: None,
+            movements: Vec::new(),
+            milk_records: Vec::new(),
+        };
+
+        if height > 0.0 {
+            animal.measurements.push(Measurement {
+                measured_at: now,
+                weight: None,
+                height: Some(height),
+                body_condition_score: None,
+                recorded_by: None,
+            });
+        }
+        animal
+    }
+
+    // The editable details of the animal, as taken by update_animal
+    fn details(&self) -> LivestockPayload {
+        LivestockPayload {
+            breed: self.breed.clone(),
+            height: self.height,
+            sex: self.sex,
+            species: self.species,
+            date_of_birth: self.date_of_birth,
+        }
+    }
+
+    fn summary(&self) -> AnimalSummary {
+        AnimalSummary {
+            id: self.id,
+            breed: self.breed.clone(),
+            sex: self.sex,
+            species: self.species,
+            health_status: self.healthstatus,
+            in_herd: self.in_herd(),
+        }
+    }
+
+    // Age of the animal at the given time, or when it left the herd if it left before
+    fn age_at(&self, now: u64) -> Age {
+        let until = self.left_herd_at().map_or(now, |date| date.min(now));
+        let days = until.saturating_sub(self.date_of_birth) / NANOS_PER_DAY;
+        Age {
+            days: days.min(u32::MAX as u64) as u32,
+            months: (days as f64 / DAYS_PER_MONTH) as u32,
+            years: (days as f64 / DAYS_PER_YEAR) as u32,
+        }
+    }
+
+    fn in_herd(&self) -> bool {
+        self.disposition.is_none()
+    }
+
+    fn left_herd_at(&self) -> Option<u64> {
+        self.disposition.as_ref().map(|disposition| disposition.date)
+    }
+}
+
+// Errors returned by the canister endpoints so callers can tell the failure cases apart
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug, PartialEq)]
+enum LivestockError {
+    NotFound { msg: String },
+    InvalidInput { msg: String },
+    DuplicateParent { msg: String },
+    Unauthorized { msg: String },
+    InbreedingLimit { msg: String },
+    UnderWithdrawal { msg: String },
+}
+
+// Roles a principal can hold on the farm
+#[derive(candid::CandidType, Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
+enum Role {
+    Owner,     // Full access, manages roles and deletes animals
+    Vet,       // Records medication and health status updates
+    Farmhand,  // Day to day animal records and breeding
+    Viewer,    // Read only access
+}
+
+// Roles allowed to call the animal record endpoints
+const ANIMAL_MANAGERS: &[Role] = &[Role::Owner, Role::Vet, Role::Farmhand];
+
+// Roles allowed to call the query endpoints
+const READERS: &[Role] = &[Role::Owner, Role::Vet, Role::Farmhand, Role::Viewer];
+
+// Health status struct of the animal
+#[derive(candid::CandidType, Clone, Serialize, Deserialize, Copy, Default)]
+#[derive(Debug)]
+#[derive(PartialEq)]
+#[derive(Hash)]
+#[derive(Eq)]
+enum HealthStatus {
+    #[default]  // Default status is Healthy
+    Healthy,
+    Sick,
+    Critical,
+    Recovering,
+}
+
+
+// Using HashMap to store animal records where each animal has a unique ID.
+// Fields missing from a state saved by an older version fall back to their defaults on upgrade.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+struct LivestockManagementSystem {
+    animal: HashMap<u64, Livestock>,   // Strores animals by their id
+    next_id: u64,   // This is a counter to generate unique IDs
+    health_alerts: Vec<HealthAlert>,  // Stores health alerts, ordered by id
+    next_alert_id: u64,  // Counter for health alert IDs
+    alert_escalation_hours: u32,  // Hours an alert for a sick animal can stay unacknowledged before it is escalated
+    health_transitions: Vec<HealthTransition>,  // Health status changes update_health_status allows
+    next_health_record_id: u64,  // Counter for health record IDs, unique across the herd
+    events: VecDeque<EventLog>,  // Stores event logs, oldest first
+    next_event_id: u64,  // Counter for event log IDs
+    event_retention: EventRetention,  // Limits the size of the event log
+    roles: HashMap<Principal, Role>,  // Stores the role granted to each principal
+    next_medication_id: u64,  // Counter for medication IDs, unique across the herd
+    vaccine_protocols: HashMap<u64, VaccineProtocol>,  // Stores vaccine protocols by their id
+    next_protocol_id: u64,  // Counter for vaccine protocol IDs
+    vaccination_alerts: HashSet<(u64, u64, u32)>,  // Overdue doses already alerted, as (animal, protocol, dose)
+    next_milk_record_id: u64,  // Counter for milk record IDs, unique across the herd
+    mastitis_cell_count: u32,  // Somatic cell count from which a milking raises a mastitis alert
+    mastitis_alerts: HashMap<u64, u64>,  // Latest mastitis alert raised per animal
+    inbreeding_policy: InbreedingPolicy,  // Threshold checked when a dam is served
+    next_breeding_event_id: u64,  // Counter for breeding event IDs
+    growth_benchmarks: HashMap<String, f64>,  // Minimum average daily gain in kg per breed, keyed by lowercase breed
+    withdrawal_periods: HashMap<String, WithdrawalPeriod>,  // Withdrawal periods per medication product, keyed by lowercase name
+    locations: HashMap<u64, Location>,  // Stores barns, pens and paddocks by their id
+    next_location_id: u64,  // Counter for location IDs
+    groups: HashMap<u64, HerdGroup>,  // Stores herd groups by their id
+    next_group_id: u64,  // Counter for herd group IDs
+    feed_items: HashMap<u64, FeedItem>,  // Stores the feed inventory by id
+    next_feed_id: u64,  // Counter for feed item IDs
+    ration_plans: Vec<RationPlan>,  // Ration plans of every group, ordered by id
+    next_ration_id: u64,  // Counter for ration plan IDs
+    feed_deliveries: Vec<FeedDelivery>,  // Feed handed out, ordered by id
+    next_feed_delivery_id: u64,  // Counter for feed delivery IDs
+    #[serde(default)]
+    state_version: u32,  // Layout version of the saved state
+}
+
+impl Default for LivestockManagementSystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl LivestockManagementSystem {
+
+    // function to initiliaze new management system
+    fn new() -> Self { LivestockManagementSystem {
+        animal: HashMap::new(),
+        next_id: 1,
+        health_alerts: Vec::new(),
+        next_alert_id: 1,
+        alert_escalation_hours: DEFAULT_ALERT_ESCALATION_HOURS,
+        health_transitions: default_health_transitions(),
+        next_health_record_id: 1,
+        events: VecDeque::new(),
+        next_event_id: 1,
+        event_retention: EventRetention::default(),
+        roles: HashMap::new(),
+        next_medication_id: 1,
+        vaccine_protocols: HashMap::new(),
+        next_protocol_id: 1,
+        vaccination_alerts: HashSet::new(),
+        next_milk_record_id: 1,
+        mastitis_cell_count: DEFAULT_MASTITIS_CELL_COUNT,
+        mastitis_alerts: HashMap::new(),
+        inbreeding_policy: InbreedingPolicy::default(),
+        next_breeding_event_id: 1,
+        growth_benchmarks: HashMap::new(),
+        withdrawal_periods: HashMap::new(),
+        locations: HashMap::new(),
+        next_location_id: 1,
+        groups: HashMap::new(),
+        next_group_id: 1,
+        feed_items: HashMap::new(),
+        next_feed_id: 1,
+        ration_plans: Vec::new(),
+        next_ration_id: 1,
+        feed_deliveries: Vec::new(),
+        next_feed_delivery_id: 1,
+        state_version: STATE_VERSION,
+    }}
+
+    // Checks that the caller holds one of the allowed roles
+    fn authorize(&self, caller: Principal, allowed: &[Role]) -> Result<(), LivestockError> {
+        match self.roles.get(&caller) {
+            Some(role) if allowed.contains(role) => Ok(()),
+            Some(role) => Err(LivestockError::Unauthorized {
+                msg: format!("principal {} with role {:?} is not allowed, requires one of {:?}", caller, role, allowed),
+            }),
+            None => Err(LivestockError::Unauthorized {
+                msg: format!("principal {} has no role, requires one of {:?}", caller, allowed),
+            }),
+        }
+    }
+
+    // Grants a role to a principal, replacing any role it already had
+    fn grant_role(&mut self, caller: Principal, principal: Principal, role: Role) -> Result<(), LivestockError> {
+        if principal == Principal::anonymous() {
+            return Err(LivestockError::InvalidInput {
+                msg: "roles cannot be granted to the anonymous principal".to_string(),
+            });
+        }
+        if role != Role::Owner && self.is_last_owner(principal) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("principal {} is the last owner and cannot be demoted", principal),
+            });
+        }
+
+        self.roles.insert(principal, role);
+        self.log_event(caller, None, LivestockEvent::RoleGranted { principal, role });
+        Ok(())
+    }
+
+    // Revokes the role of a principal
+    fn revoke_role(&mut self, caller: Principal, principal: Principal) -> Result<Role, LivestockError> {
+        if self.is_last_owner(principal) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("principal {} is the last owner and cannot be revoked", principal),
+            });
+        }
+
+        let role = self.roles.remove(&principal).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("principal {} has no role", principal),
+        })?;
+        self.log_event(caller, None, LivestockEvent::RoleRevoked { principal, role });
+        Ok(role)
+    }
+
+    // Whether the principal is the only remaining owner
+    fn is_last_owner(&self, principal: Principal) -> bool {
+        self.roles.get(&principal) == Some(&Role::Owner)
+            && self.roles.values().filter(|role| **role == Role::Owner).count() == 1
+    }
+
+    // Appends an entry to the event log and drops the oldest entries above the retention limit
+    fn log_event(&mut self, actor: Principal, animal_id: Option<u64>, event: LivestockEvent) {
+        self.events.push_back(EventLog {
+            id: self.next_event_id,
+            timestamp: time(),
+            actor,
+            animal_id,
+            changes: event.changes(),
+            event,
+        });
+        self.next_event_id += 1;
+        while self.events.len() as u64 > self.event_retention.max_entries {
+            self.events.pop_front();
+        }
+    }
+
+    // Drops the entries older than the retention policy allows, returns how many were dropped
+    fn compact_events(&mut self, actor: Principal, now: u64) -> u64 {
+        let Some(max_age_days) = self.event_retention.max_age_days else {
+            return 0;
+        };
+        let older_than = now.saturating_sub(max_age_days as u64 * NANOS_PER_DAY);
+        let expired = self.events.partition_point(|event| event.timestamp < older_than);
+        if expired == 0 {
+            return 0;
+        }
+        self.events.drain(..expired);
+        self.log_event(actor, None, LivestockEvent::EventsCompacted { removed: expired as u64, older_than });
+        expired as u64
+    }
+
+    // function to change how much of the event log is kept, entries beyond it are dropped right away
+    fn set_event_retention(&mut self, caller: Principal, retention: EventRetention) -> Result<EventRetention, LivestockError> {
+        if retention.max_entries == 0 || retention.max_entries > MAX_EVENT_LOG_ENTRIES {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("max_entries has to be between 1 and {}", MAX_EVENT_LOG_ENTRIES),
+            });
+        }
+        if retention.max_age_days == Some(0) {
+            return Err(LivestockError::InvalidInput {
+                msg: "max_age_days has to be at least 1".to_string(),
+            });
+        }
+        let before = self.event_retention;
+        self.event_retention = retention;
+        self.log_event(caller, None, LivestockEvent::EventRetentionChanged { before, after: retention });
+        self.compact_events(caller, time());
+        Ok(retention)
+    }
+
+    // function to get one page of the event log entries matching a query, newest first
+    fn get_event_logs(&self, query: &EventQuery, cursor: Option<u64>, limit: Option<u32>) -> Result<EventPage, LivestockError> {
+        let limit = page_size(limit)?;
+        let mut page: Vec<EventLog> = self
+            .events
+            .iter()
+            .rev()
+            .skip_while(|event| matches!(cursor, Some(cursor) if event.id >= cursor))
+            .filter(|event| {
+                !matches!(query.animal_id, Some(id) if event.animal_id != Some(id))
+                    && !matches!(query.kind, Some(kind) if event.event.kind() != kind)
+                    && !matches!(query.from, Some(from) if event.timestamp < from)
+                    && !matches!(query.to, Some(to) if event.timestamp > to)
+            })
+            .take(limit + 1)
+            .cloned()
+            .collect();
+        let next_cursor = if page.len() > limit {
+            page.truncate(limit);
+            page.last().map(|event| event.id)
+        } else {
+            None
+        };
+        Ok(EventPage { events: page, next_cursor })
+    }
+
+    // function to get the audit trail of an animal one page at a time, oldest entries first
+    fn get_audit_trail(&self, animal_id: u64, cursor: Option<u64>, limit: Option<u32>) -> Result<EventPage, LivestockError> {
+        let limit = page_size(limit)?;
+        let start = cursor.map_or(0, |cursor| self.events.partition_point(|event| event.id <= cursor));
+        let mut page: Vec<EventLog> = self
+            .events
+            .range(start..)
+            .filter(|event| event.animal_id == Some(animal_id))
+            .take(limit + 1)
+            .cloned()
+            .collect();
+        let next_cursor = if page.len() > limit {
+            page.truncate(limit);
+            page.last().map(|event| event.id)
+        } else {
+            None
+        };
+        Ok(EventPage { events: page, next_cursor })
+    }
+
+    // create_animal function
+    fn create_animal(&mut self, caller: Principal, payload: LivestockPayload) -> Result<u64, LivestockError> {
+        validate_animal_details(&payload)?;
+        let id = self.allocate_animal_id()?;
+        self.add_animal(caller, Livestock::new(caller, id, payload, time()));
+
+        // Return the ID of the new animal for reference
+        Ok(id)
+    }
+
+    // Inserts a new animal and logs its creation
+    fn add_animal(&mut self, caller: Principal, animal: Livestock) {
+        let (id, species, sex, date_of_birth) = (animal.id, animal.species, animal.sex, animal.date_of_birth);
+        let breed = animal.breed.clone();
+        self.animal.insert(id, animal);
+        self.log_event(caller, Some(id), LivestockEvent::AnimalCreated { breed, species, sex, date_of_birth });
+    }
+
+    // Hands out the next animal ID, failing instead of wrapping once the ID space is used up
+    fn allocate_animal_id(&mut self) -> Result<u64, LivestockError> {
+        Ok(self.allocate_animal_ids(1)?[0])
+    }
+
+    // Hands out a block of consecutive animal IDs, or none of them if the ID space cannot hold them all
+    fn allocate_animal_ids(&mut self, count: usize) -> Result<Vec<u64>, LivestockError> {
+        let first = self.next_id;
+        self.next_id = first.checked_add(count as u64).ok_or_else(|| LivestockError::InvalidInput {
+            msg: "no animal IDs left".to_string(),
+        })?;
+        Ok((first..self.next_id).collect())
+    }
+
+    // Looks up an animal by ID
+    fn get_animal(&self, id: u64) -> Result<&Livestock, LivestockError> {
+        self.animal.get(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("an animal with id={} not found", id),
+        })
+    }
+
+    // Mutable lookup of an animal by ID
+    fn get_animal_mut(&mut self, id: u64) -> Result<&mut Livestock, LivestockError> {
+        self.animal.get_mut(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("an animal with id={} not found", id),
+        })
+    }
+
+    // function to record the service of a dam by a sire, the start of a breeding event
+    fn record_service(&mut self, caller: Principal, dam_id: u64, sire_id: u64, service_date: u64, method: ServiceMethod) -> Result<BreedingEvent, LivestockError> {
+        self.validate_parents(None, sire_id, dam_id)?;
+
+        let dam = self.get_animal(dam_id)?;
+        let sire = self.get_animal(sire_id)?;
+        if dam.sex != Sex::Female || sire.sex != Sex::Male {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} has to be the female and animal with id={} the male", dam_id, sire_id),
+            });
+        }
+
+        // both parents have to be in the herd to breed
+        for parent in [dam, sire] {
+            if !parent.in_herd() {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("parent animal with id={} is no longer in the herd", parent.id),
+                });
+            }
+        }
+        if service_date > time() {
+            return Err(LivestockError::InvalidInput {
+                msg: "service date cannot be in the future".to_string(),
+            });
+        }
+        if dam.breeding_events.iter().any(|event| event.status == BreedingStatus::Pregnant) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is already pregnant", dam_id),
+            });
+        }
+
+        // check the inbreeding of the offspring against the policy
+        let coefficient = self.inbreeding_coefficient(sire_id, dam_id);
+        let exceeds_threshold = coefficient > self.inbreeding_policy.threshold;
+        if exceeds_threshold && self.inbreeding_policy.action == InbreedingAction::Refuse {
+            return Err(LivestockError::InbreedingLimit {
+                msg: format!(
+                    "offspring of animals with id={} and id={} would have an inbreeding coefficient of {:.4}, above the threshold of {:.4}",
+                    dam_id, sire_id, coefficient, self.inbreeding_policy.threshold
+                ),
+            });
+        }
+
+        let event = BreedingEvent {
+            id: self.next_breeding_event_id,
+            sire_id,
+            method,
+            service_date,
+            expected_due_date: service_date + dam.species.gestation_days() * NANOS_PER_DAY,
+            status: BreedingStatus::Served,
+            pregnancy_checked_at: None,
+            birth_date: None,
+            offspring_ids: Vec::new(),
+        };
+        self.next_breeding_event_id += 1;
+        self.get_animal_mut(dam_id)?.breeding_events.push(event.clone());
+
+        // log the event
+        self.log_event(caller, Some(dam_id), LivestockEvent::AnimalServed {
+            breeding_event_id: event.id,
+            sire_id,
+            method,
+            expected_due_date: event.expected_due_date,
+        });
+        if exceeds_threshold {
+            let threshold = self.inbreeding_policy.threshold;
+            self.log_event(caller, Some(dam_id), LivestockEvent::InbreedingWarning { sire_id, inbreeding_coefficient: coefficient, threshold });
+        }
+
+        Ok(event)
+    }
+
+    // function to record the result of a pregnancy check on a breeding event
+    fn record_pregnancy_check(&mut self, caller: Principal, dam_id: u64, breeding_event_id: u64, pregnant: bool) -> Result<BreedingEvent, LivestockError> {
+        let now = time();
+        let dam = self.get_animal_mut(dam_id)?;
+        let event = find_breeding_event(dam, breeding_event_id)?;
+        if !matches!(event.status, BreedingStatus::Served | BreedingStatus::Pregnant) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("breeding event with id={} is {:?}", breeding_event_id, event.status),
+            });
+        }
+
+        event.pregnancy_checked_at = Some(now);
+        event.status = if pregnant { BreedingStatus::Pregnant } else { BreedingStatus::NotPregnant };
+        let event = event.clone();
+        dam.reproductive_status = if pregnant {
+            ReproductiveStatus::Pregnant { expected_due_date: event.expected_due_date }
+        } else {
+            ReproductiveStatus::Open
+        };
+        dam.updated_at = Some(now);
+
+        self.log_event(caller, Some(dam_id), LivestockEvent::PregnancyChecked { breeding_event_id, pregnant });
+
+        Ok(event)
+    }
+
+    // function to record a birth, creating the offspring with the sire and dam of the breeding event as parents
+    fn record_birth(&mut self, caller: Principal, dam_id: u64, breeding_event_id: u64, birth_date: u64, offspring: Vec<OffspringPayload>) -> Result<Vec<u64>, LivestockError> {
+        if offspring.is_empty() || offspring.len() > MAX_LITTER_SIZE {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("a birth records between 1 and {} offspring, got {}", MAX_LITTER_SIZE, offspring.len()),
+            });
+        }
+        if let Some(calf) = offspring.iter().find(|calf| !calf.birth_weight.is_finite() || calf.birth_weight <= 0.0) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("birth weight must be a positive number, got {}", calf.birth_weight),
+            });
+        }
+
+        let dam = self.get_animal_mut(dam_id)?;
+        let (dam_breed, species) = (dam.breed.clone(), dam.species);
+        let event = find_breeding_event(dam, breeding_event_id)?;
+        if !matches!(event.status, BreedingStatus::Served | BreedingStatus::Pregnant) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("breeding event with id={} is {:?}", breeding_event_id, event.status),
+            });
+        }
+        if birth_date < event.service_date || birth_date > time() {
+            return Err(LivestockError::InvalidInput {
+                msg: "birth date has to be between the service date and now".to_string(),
+            });
+        }
+        let sire_id = event.sire_id;
+
+        // Every offspring is checked and given an ID before anything is recorded, so a birth is
+        // recorded with its whole litter or not at all
+        let mut litter = Vec::new();
+        for calf in offspring {
+            let details = LivestockPayload {
+                breed: calf.breed.filter(|breed| !breed.trim().is_empty()).unwrap_or_else(|| dam_breed.clone()),
+                height: 0.0,
+                sex: calf.sex,
+                species,
+                date_of_birth: birth_date,
+            };
+            validate_animal_details(&details)?;
+            litter.push((details, calf.birth_weight));
+        }
+        let offspring_ids = self.allocate_animal_ids(litter.len())?;
+
+        // Create the offspring and link them to their parents
+        let now = time();
+        for (&offspring_id, (details, birth_weight)) in offspring_ids.iter().zip(litter) {
+            let mut newborn = Livestock::new(caller, offspring_id, details, now);
+            newborn.parent_ids = Some(ParentIds {
+                parent1_id: sire_id,
+                parent2_id: dam_id,
+            });
+            newborn.birth_weight = Some(birth_weight);
+            newborn.measurements.push(Measurement {
+                measured_at: birth_date,
+                weight: Some(birth_weight),
+                height: None,
+                body_condition_score: None,
+                recorded_by: None,
+            });
+            self.add_animal(caller, newborn);
+        }
+
+        let dam = self.get_animal_mut(dam_id)?;
+        dam.reproductive_status = ReproductiveStatus::Lactating { since: birth_date };
+        dam.updated_at = Some(now);
+        let event = find_breeding_event(dam, breeding_event_id)?;
+        event.status = BreedingStatus::Delivered;
+        event.birth_date = Some(birth_date);
+        event.offspring_ids = offspring_ids.clone();
+
+        // log the event
+        self.log_event(caller, Some(dam_id), LivestockEvent::BirthRecorded {
+            breeding_event_id,
+            sire_id,
+            offspring_ids: offspring_ids.clone(),
+        });
+
+        Ok(offspring_ids)
+    }
+
+    // Births expected from served or pregnant dams within the given number of days, overdue ones included
+    fn expected_births(&self, within_days: u32, now: u64) -> Vec<ExpectedBirth> {
+        let horizon = now.saturating_add((within_days as u64).saturating_mul(NANOS_PER_DAY));
+        let mut births: Vec<ExpectedBirth> = self
+            .animal
+            .values()
+            .filter(|dam| dam.in_herd())
+            .flat_map(|dam| {
+                dam.breeding_events
+                    .iter()
+                    .filter(|event| matches!(event.status, BreedingStatus::Served | BreedingStatus::Pregnant))
+                    .map(move |event| ExpectedBirth {
+                        dam_id: dam.id,
+                        sire_id: event.sire_id,
+                        breeding_event_id: event.id,
+                        status: event.status,
+                        expected_due_date: event.expected_due_date,
+                    })
+            })
+            .filter(|birth| birth.expected_due_date <= horizon)
+            .collect();
+        births.sort_by_key(|birth| (birth.expected_due_date, birth.dam_id));
+        births
+    }
+
+    // function to record the parents of an existing animal, e.g. one bought in with known pedigree
+    fn set_parents(&mut self, caller: Principal, id: u64, parent1_id: u64, parent2_id: u64) -> Result<Livestock, LivestockError> {
+        self.validate_parents(Some(id), parent1_id, parent2_id)?;
+
+        let animal = self.get_animal_mut(id)?;
+        let parents = ParentIds {
+            parent1_id,
+            parent2_id,
+        };
+        let before = animal.parent_ids.replace(parents.clone());
+        animal.updated_at = Some(time());
+        let updated = animal.clone();
+
+        self.log_event(caller, Some(id), LivestockEvent::ParentsSet { before, after: parents });
+
+        Ok(updated)
+    }
+
+    // Checks a parent assignment: two different existing animals, neither of them the child or one of its descendants
+    fn validate_parents(&self, child_id: Option<u64>, parent1_id: u64, parent2_id: u64) -> Result<(), LivestockError> {
+
+        // an animal cannot be bred with itself
+        if parent1_id == parent2_id {
+            return Err(LivestockError::DuplicateParent {
+                msg: format!("both parents have id={}", parent1_id),
+            });
+        }
+
+        // check if both parents exist
+        for parent_id in [parent1_id, parent2_id] {
+            if self.get_animal(parent_id).is_err() {
+                return Err(LivestockError::NotFound {
+                    msg: format!("parent animal with id={} not found", parent_id),
+                });
+            }
+            if let Some(child_id) = child_id {
+                if parent_id == child_id || self.is_ancestor(child_id, parent_id) {
+                    return Err(LivestockError::InvalidInput {
+                        msg: format!("animal with id={} is animal with id={} or one of its descendants and cannot be its parent", parent_id, child_id),
+                    });
+                }
+            }
+        }
+
+        // one sire and one dam of the same species
+        let parent1 = self.get_animal(parent1_id)?;
+        let parent2 = self.get_animal(parent2_id)?;
+        let sexes = [parent1.sex, parent2.sex];
+        if !(sexes.contains(&Sex::Male) && sexes.contains(&Sex::Female)) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("parents need one male and one female, got {:?} and {:?}", parent1.sex, parent2.sex),
+            });
+        }
+        if parent1.species != parent2.species {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("parents are of different species, {:?} and {:?}", parent1.species, parent2.species),
+            });
+        }
+        Ok(())
+    }
+
+    // Whether `ancestor_id` appears anywhere in the pedigree of `id`
+    fn is_ancestor(&self, ancestor_id: u64, id: u64) -> bool {
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([id]);
+        while let Some(current) = queue.pop_front() {
+            let Ok(animal) = self.get_animal(current) else { continue };
+            if let Some(parents) = &animal.parent_ids {
+                for parent_id in [parents.parent1_id, parents.parent2_id] {
+                    if parent_id == ancestor_id {
+                        return true;
+                    }
+                    if visited.insert(parent_id) {
+                        queue.push_back(parent_id);
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Get Peddigree function, builds the ancestor tree up to the given number of generations
+    fn get_pedigree(&self, id: u64, max_generations: u32) -> Result<PedigreeNode, LivestockError> {
+        let animal = self.get_animal(id)?;
+        let mut expanded = HashSet::new();
+        Ok(self.pedigree_node(animal, 0, max_generations.min(MAX_PEDIGREE_GENERATIONS), &mut expanded))
+    }
+
+    // Builds one node of the pedigree tree. The expanded set keeps every ancestor to a single
+    // expansion, which also stops the walk if the parent links ever form a cycle.
+    fn pedigree_node(&self, animal: &Livestock, generation: u32, max_generations: u32, expanded: &mut HashSet<u64>) -> PedigreeNode {
+        let repeated = !expanded.insert(animal.id);
+        let mut parents = Vec::new();
+        if !repeated && generation < max_generations {
+            if let Some(parent_ids) = &animal.parent_ids {
+                for parent_id in [parent_ids.parent1_id, parent_ids.parent2_id] {
+                    if let Ok(parent) = self.get_animal(parent_id) {
+                        parents.push(self.pedigree_node(parent, generation + 1, max_generations, expanded));
+                    }
+                }
+            }
+        }
+        PedigreeNode {
+            animal: animal.summary(),
+            generation,
+            repeated,
+            parents,
+        }
+    }
+
+    // Wright inbreeding coefficient of the offspring of two animals, i.e. their coefficient of coancestry
+    fn inbreeding_coefficient(&self, parent1_id: u64, parent2_id: u64) -> f64 {
+        let mut memo = HashMap::new();
+        self.kinship(parent1_id, parent2_id, 0, &mut memo)
+    }
+
+    // Coefficient of coancestry of two animals, worked out recursively over the parent links.
+    // Animals with unknown parents are treated as unrelated, non inbred founders, and the
+    // recursion stops after twice the pedigree depth limit. A pair reached at different depths
+    // can be cut off at one and not the other, so the memo is keyed by depth as well.
+    fn kinship(&self, a: u64, b: u64, depth: u32, memo: &mut HashMap<(u64, u64, u32), f64>) -> f64 {
+        if depth > 2 * MAX_PEDIGREE_GENERATIONS {
+            return 0.0;
+        }
+        let key = (a.min(b), a.max(b), depth);
+        if let Some(value) = memo.get(&key) {
+            return *value;
+        }
+
+        let value = if a == b {
+            // an animal with itself: 1/2 (1 + F), F being its own inbreeding coefficient
+            let inbreeding = match self.parents_of(a) {
+                Some((sire, dam)) => self.kinship(sire, dam, depth + 1, memo),
+                None => 0.0,
+            };
+            0.5 * (1.0 + inbreeding)
+        } else {
+            // expand whichever animal is not an ancestor of the other
+            let (younger, other) = if self.is_ancestor(a, b) { (b, a) } else { (a, b) };
+            match self.parents_of(younger) {
+                Some((sire, dam)) => 0.5 * (self.kinship(other, sire, depth + 1, memo) + self.kinship(other, dam, depth + 1, memo)),
+                None => 0.0,
+            }
+        };
+        memo.insert(key, value);
+        value
+    }
+
+    // Known parents of an animal
+    fn parents_of(&self, id: u64) -> Option<(u64, u64)> {
+        self.get_animal(id)
+            .ok()
+            .and_then(|animal| animal.parent_ids.as_ref())
+            .map(|parents| (parents.parent1_id, parents.parent2_id))
+    }
+
+    // Animals of the opposite sex and same species in the herd ranked by the inbreeding coefficient of
+    // their offspring with the animal. Only those candidates are scored, the rest of the herd is skipped.
+    fn suggest_mates(&self, id: u64, limit: Option<u32>) -> Result<Vec<MateSuggestion>, LivestockError> {
+        let limit = page_size(Some(limit.unwrap_or(DEFAULT_MATE_SUGGESTIONS)))?;
+        let animal = self.get_animal(id)?;
+        let mate_sex = match animal.sex {
+            Sex::Male => Sex::Female,
+            Sex::Female => Sex::Male,
+            Sex::Unknown => {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("animal with id={} has no known sex", id),
+                })
+            }
+        };
+
+        let mut memo = HashMap::new();
+        let mut suggestions: Vec<MateSuggestion> = self
+            .animal
+            .values()
+            .filter(|candidate| candidate.in_herd() && candidate.sex == mate_sex && candidate.species == animal.species)
+            .map(|candidate| MateSuggestion {
+                animal_id: candidate.id,
+                breed: candidate.breed.clone(),
+                inbreeding_coefficient: self.kinship(id, candidate.id, 0, &mut memo),
+            })
+            .collect();
+        suggestions.sort_by(|a, b| {
+            a.inbreeding_coefficient
+                .total_cmp(&b.inbreeding_coefficient)
+                .then(a.animal_id.cmp(&b.animal_id))
+        });
+        suggestions.truncate(limit);
+        Ok(suggestions)
+    }
+
+    // function to change the inbreeding policy used by record_service
+    fn set_inbreeding_policy(&mut self, caller: Principal, policy: InbreedingPolicy) -> Result<InbreedingPolicy, LivestockError> {
+        if !policy.threshold.is_finite() || !(0.0..=1.0).contains(&policy.threshold) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("inbreeding threshold must be between 0 and 1, got {}", policy.threshold),
+            });
+        }
+        let before = self.inbreeding_policy;
+        self.inbreeding_policy = policy;
+        self.log_event(caller, None, LivestockEvent::InbreedingPolicyChanged { before, after: policy });
+        Ok(policy)
+    }
+
+    // Children of an animal
+    fn children_of(&self, id: u64) -> Vec<&Livestock> {
+        let mut children: Vec<&Livestock> = self
+            .animal
+            .values()
+            .filter(|animal| matches!(&animal.parent_ids, Some(parents) if parents.parent1_id == id || parents.parent2_id == id))
+            .collect();
+        children.sort_by_key(|animal| animal.id);
+        children
+    }
+
+    // Children, grandchildren and further offspring of an animal up to the given number of generations
+    fn get_descendants(&self, id: u64, max_generations: u32) -> Result<Vec<Descendant>, LivestockError> {
+        self.get_animal(id)?;
+        let max_generations = max_generations.min(MAX_PEDIGREE_GENERATIONS);
+
+        let mut descendants = Vec::new();
+        let mut visited = HashSet::from([id]);
+        let mut queue = VecDeque::from([(id, 0)]);
+        while let Some((current, generation)) = queue.pop_front() {
+            if generation >= max_generations {
+                continue;
+            }
+            for child in self.children_of(current) {
+                if visited.insert(child.id) {
+                    descendants.push(Descendant {
+                        animal: child.summary(),
+                        generation: generation + 1,
+                    });
+                    queue.push_back((child.id, generation + 1));
+                }
+            }
+        }
+        Ok(descendants)
+    }
+
+    // function to update the animal details
+    fn update_animal(&mut self, caller: Principal, id: u64, payload: LivestockPayload) -> Result<Livestock, LivestockError> {
+        validate_animal_details(&payload)?;
+
+        let animal = self.get_animal_mut(id)?;
+        if payload.sex != Sex::Female && animal.reproductive_status != ReproductiveStatus::Open {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} has a reproductive status and has to stay female", id),
+            });
+        }
+        let before = animal.details();
+        let after = payload.clone();
+        let LivestockPayload { breed, height, sex, species, date_of_birth } = payload;
+
+        // a changed height goes into the measurement history
+        if height != animal.height {
+            animal.measurements.push(Measurement {
+                measured_at: time(),
+                weight: None,
+                height: Some(height),
+                body_condition_score: None,
+                recorded_by: Some(caller),
+            });
+        }
+        animal.breed = breed;
+        animal.height = height;
+        animal.sex = sex;
+        animal.species = species;
+        animal.date_of_birth = date_of_birth;
+        animal.updated_at = Some(time());
+        let updated = animal.clone();
+
+        // log the event
+        self.log_event(caller, Some(id), LivestockEvent::AnimalUpdated { before, after });
+
+        Ok(updated)
+    }
+
+    // function to replace the tags of an animal
+    fn set_tags(&mut self, caller: Principal, id: u64, tags: Vec<String>) -> Result<Livestock, LivestockError> {
+        let mut normalized: Vec<String> = Vec::new();
+        for tag in tags {
+            let tag = tag.trim().to_lowercase();
+            if tag.is_empty() || tag.chars().count() > MAX_TAG_LENGTH {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("tags have to be between 1 and {} characters long", MAX_TAG_LENGTH),
+                });
+            }
+            if !normalized.contains(&tag) {
+                normalized.push(tag);
+            }
+        }
+        if normalized.len() > MAX_TAGS {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("an animal can carry at most {} tags", MAX_TAGS),
+            });
+        }
+
+        let animal = self.get_animal_mut(id)?;
+        let before = std::mem::replace(&mut animal.tags, normalized.clone());
+        animal.updated_at = Some(time());
+        let updated = animal.clone();
+
+        self.log_event(caller, Some(id), LivestockEvent::TagsUpdated { before, after: normalized });
+
+        Ok(updated)
+    }
+
+    // function to give an animal an identifier of a kind it does not carry yet
+    fn add_identifier(&mut self, caller: Principal, id: u64, kind: IdentifierKind, value: String) -> Result<Identifier, LivestockError> {
+        let value = normalize_identifier(kind, &value)?;
+        self.check_identifier_unused(kind, &value)?;
+        let animal = self.get_animal_mut(id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", id),
+            });
+        }
+        if animal.identifiers.iter().any(|identifier| identifier.kind == kind && identifier.is_active()) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} already carries a {:?} identifier, retag it instead", id, kind),
+            });
+        }
+
+        let now = time();
+        let identifier = Identifier {
+            kind,
+            value,
+            assigned_at: now,
+            assigned_by: caller,
+            retired_at: None,
+            retired_by: None,
+            retire_reason: String::new(),
+        };
+        animal.identifiers.push(identifier.clone());
+        animal.updated_at = Some(now);
+
+        self.log_event(caller, Some(id), LivestockEvent::IdentifierAdded { identifier: identifier.clone() });
+        Ok(identifier)
+    }
+
+    // function to replace the identifier of a kind an animal carries, the old one is kept as retired
+    fn retag_animal(&mut self, caller: Principal, id: u64, kind: IdentifierKind, value: String, reason: String) -> Result<Identifier, LivestockError> {
+        let value = normalize_identifier(kind, &value)?;
+        if reason.chars().count() > MAX_NOTE_LENGTH {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("reason cannot be longer than {} characters", MAX_NOTE_LENGTH),
+            });
+        }
+        self.check_identifier_unused(kind, &value)?;
+        let now = time();
+        let animal = self.get_animal_mut(id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", id),
+            });
+        }
+        let current = animal
+            .identifiers
+            .iter_mut()
+            .find(|identifier| identifier.kind == kind && identifier.is_active())
+            .ok_or_else(|| LivestockError::NotFound {
+                msg: format!("animal with id={} carries no {:?} identifier", id, kind),
+            })?;
+        current.retired_at = Some(now);
+        current.retired_by = Some(caller);
+        current.retire_reason = reason;
+        let before = current.clone();
+
+        let identifier = Identifier {
+            kind,
+            value,
+            assigned_at: now,
+            assigned_by: caller,
+            retired_at: None,
+            retired_by: None,
+            retire_reason: String::new(),
+        };
+        animal.identifiers.push(identifier.clone());
+        animal.updated_at = Some(now);
+
+        self.log_event(caller, Some(id), LivestockEvent::AnimalRetagged { before, after: identifier.clone() });
+        Ok(identifier)
+    }
+
+    // Identifiers are unique per kind across the herd, retired ones included
+    fn check_identifier_unused(&self, kind: IdentifierKind, value: &str) -> Result<(), LivestockError> {
+        match self.find_animal_by_identifier(kind, value) {
+            Ok(animal) => Err(LivestockError::InvalidInput {
+                msg: format!("{:?} identifier {} is already assigned to animal with id={}", kind, value, animal.id),
+            }),
+            Err(_) => Ok(()),
+        }
+    }
+
+    // Looks up the animal that carries, or once carried, an identifier
+    fn find_animal_by_identifier(&self, kind: IdentifierKind, value: &str) -> Result<&Livestock, LivestockError> {
+        let value = normalize_identifier(kind, value)?;
+        self.animal
+            .values()
+            .find(|animal| animal.identifiers.iter().any(|identifier| identifier.kind == kind && identifier.value == value))
+            .ok_or_else(|| LivestockError::NotFound {
+                msg: format!("no animal with {:?} identifier {}", kind, value),
+            })
+    }
+
+    // function to add a barn, pen or paddock
+    fn add_location(&mut self, caller: Principal, payload: LocationPayload) -> Result<Location, LivestockError> {
+        let location = Location {
+            id: self.next_location_id,
+            name: payload.name.trim().to_string(),
+            kind: payload.kind,
+            parent_id: payload.parent_id,
+            capacity: payload.capacity,
+        };
+        self.validate_location(&location)?;
+        self.locations.insert(location.id, location.clone());
+        self.next_location_id += 1;
+
+        self.log_event(caller, None, LivestockEvent::LocationAdded { location: location.clone() });
+        Ok(location)
+    }
+
+    // function to rename a location, change its kind or capacity, or move it under another location
+    fn update_location(&mut self, caller: Principal, id: u64, payload: LocationPayload) -> Result<Location, LivestockError> {
+        let before = self.get_location(id)?.clone();
+        let location = Location {
+            id,
+            name: payload.name.trim().to_string(),
+            kind: payload.kind,
+            parent_id: payload.parent_id,
+            capacity: payload.capacity,
+        };
+        self.validate_location(&location)?;
+        if location.parent_id.is_some_and(|parent_id| self.is_within(Some(parent_id), id)) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("location with id={} cannot be placed inside itself", id),
+            });
+        }
+        let occupancy = self.occupancy(id);
+        if matches!(location.capacity, Some(capacity) if occupancy > capacity as u64) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("location with id={} already holds {} animals", id, occupancy),
+            });
+        }
+        // The animals in this location now also count against the capacity of its new parents
+        if location.parent_id != before.parent_id {
+            for ancestor in self.location_chain(location.parent_id) {
+                let inside = self.animal.values().filter(|animal| animal.in_herd() && self.is_within(animal.location_id, id) && !self.is_within(animal.location_id, ancestor.id)).count() as u64;
+                self.check_capacity(ancestor, inside)?;
+            }
+        }
+        self.locations.insert(id, location.clone());
+
+        self.log_event(caller, None, LivestockEvent::LocationUpdated { before, after: location.clone() });
+        Ok(location)
+    }
+
+    // function to remove an empty location without sub-locations
+    fn remove_location(&mut self, caller: Principal, id: u64) -> Result<Location, LivestockError> {
+        self.get_location(id)?;
+        if let Some(child) = self.locations.values().find(|location| location.parent_id == Some(id)) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("location with id={} contains location with id={}", id, child.id),
+            });
+        }
+        if self.occupancy(id) > 0 {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("location with id={} still holds animals", id),
+            });
+        }
+        let location = self.locations.remove(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a location with id={} not found", id),
+        })?;
+
+        self.log_event(caller, None, LivestockEvent::LocationRemoved { location: location.clone() });
+        Ok(location)
+    }
+
+    // Checks the name and capacity of a location and that its parent exists
+    fn validate_location(&self, location: &Location) -> Result<(), LivestockError> {
+        if location.name.is_empty() || location.name.chars().count() > MAX_LOCATION_NAME_LENGTH {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("location name has to be between 1 and {} characters long", MAX_LOCATION_NAME_LENGTH),
+            });
+        }
+        if location.capacity == Some(0) {
+            return Err(LivestockError::InvalidInput {
+                msg: "capacity must be at least one animal".to_string(),
+            });
+        }
+        if let Some(parent_id) = location.parent_id {
+            self.get_location(parent_id)?;
+        }
+        Ok(())
+    }
+
+    // Looks up a location by ID
+    fn get_location(&self, id: u64) -> Result<&Location, LivestockError> {
+        self.locations.get(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a location with id={} not found", id),
+        })
+    }
+
+    // A location followed by the locations it is part of, up to the top
+    fn location_chain(&self, location_id: Option<u64>) -> Vec<&Location> {
+        let mut chain = Vec::new();
+        let mut current = location_id.and_then(|id| self.locations.get(&id));
+        // Bounded by the number of locations in case the hierarchy was ever left with a cycle
+        while let Some(location) = current {
+            if chain.len() > self.locations.len() {
+                break;
+            }
+            chain.push(location);
+            current = location.parent_id.and_then(|id| self.locations.get(&id));
+        }
+        chain
+    }
+
+    // Whether a location is the given one or one of its sub-locations
+    fn is_within(&self, location_id: Option<u64>, ancestor: u64) -> bool {
+        self.location_chain(location_id).iter().any(|location| location.id == ancestor)
+    }
+
+    // Animals in the herd kept in a location or its sub-locations
+    fn occupancy(&self, location_id: u64) -> u64 {
+        self.animal.values().filter(|animal| animal.in_herd() && self.is_within(animal.location_id, location_id)).count() as u64
+    }
+
+    // Fails if a location cannot take the given number of extra animals
+    fn check_capacity(&self, location: &Location, arriving: u64) -> Result<(), LivestockError> {
+        let occupancy = self.occupancy(location.id);
+        match location.capacity {
+            Some(capacity) if occupancy + arriving > capacity as u64 => Err(LivestockError::InvalidInput {
+                msg: format!("{} holds {} of {} animals and cannot take {} more", location.name, occupancy, capacity, arriving),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    // function to move animals to a location, or out of any location with None. The whole group moves
+    // or none of it does, animals already there are left as they are.
+    fn move_animals(&mut self, caller: Principal, ids: Vec<u64>, to: Option<u64>) -> Result<Vec<Movement>, LivestockError> {
+        if let Some(location_id) = to {
+            self.get_location(location_id)?;
+        }
+        let mut moving: Vec<u64> = Vec::new();
+        for id in ids {
+            let animal = self.get_animal(id)?;
+            if !animal.in_herd() {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("animal with id={} is no longer in the herd", id),
+                });
+            }
+            if animal.location_id != to && !moving.contains(&id) {
+                moving.push(id);
+            }
+        }
+        for location in self.location_chain(to) {
+            let arriving = moving.iter().filter(|id| self.animal.get(id).is_some_and(|animal| !self.is_within(animal.location_id, location.id))).count() as u64;
+            self.check_capacity(location, arriving)?;
+        }
+
+        let now = time();
+        let mut movements = Vec::new();
+        for id in moving {
+            let animal = self.get_animal_mut(id)?;
+            let movement = Movement {
+                animal_id: id,
+                from: animal.location_id,
+                to,
+                moved_at: now,
+                moved_by: caller,
+            };
+            animal.location_id = to;
+            animal.movements.push(movement.clone());
+            animal.updated_at = Some(now);
+            self.log_event(caller, Some(id), LivestockEvent::AnimalMoved { from: movement.from, to });
+            movements.push(movement);
+        }
+        Ok(movements)
+    }
+
+    // One page of the animals in the herd kept in a location, or also in its sub-locations, by id
+    fn animals_in_location(&self, location_id: u64, include_sub_locations: bool, cursor: Option<AnimalCursor>, limit: Option<u32>) -> Result<AnimalPage, LivestockError> {
+        let limit = page_size(limit)?;
+        self.get_location(location_id)?;
+        let mut matches: Vec<(AnimalCursor, &Livestock)> = self
+            .animal
+            .values()
+            .filter(|animal| {
+                animal.in_herd()
+                    && if include_sub_locations { self.is_within(animal.location_id, location_id) } else { animal.location_id == Some(location_id) }
+            })
+            .map(|animal| (AnimalCursor { sort_value: animal.id, id: animal.id }, animal))
+            .collect();
+        matches.sort_by_key(|(key, _)| key.id);
+        Ok(animal_page(matches, false, cursor, limit))
+    }
+
+    // Occupancy of every location against its capacity, by id
+    fn location_occupancy(&self) -> Vec<LocationOccupancy> {
+        let mut occupancy: Vec<LocationOccupancy> = self
+            .locations
+            .values()
+            .map(|location| LocationOccupancy {
+                location_id: location.id,
+                name: location.name.clone(),
+                capacity: location.capacity,
+                occupancy: self.occupancy(location.id),
+            })
+            .collect();
+        occupancy.sort_by_key(|entry| entry.location_id);
+        occupancy
+    }
+
+    // function to create a named group of animals
+    fn create_group(&mut self, caller: Principal, name: String, kind: GroupKind, member_ids: Vec<u64>) -> Result<HerdGroup, LivestockError> {
+        let name = self.validate_group_name(None, &name)?;
+        let member_ids = self.validate_members(member_ids)?;
+        let group = HerdGroup {
+            id: self.next_group_id,
+            name,
+            kind,
+            member_ids,
+            created_at: time(),
+        };
+        self.groups.insert(group.id, group.clone());
+        self.next_group_id += 1;
+
+        self.log_event(caller, None, LivestockEvent::GroupCreated { group: group.clone() });
+        Ok(group)
+    }
+
+    // function to rename a group or change its kind
+    fn update_group(&mut self, caller: Principal, id: u64, name: String, kind: GroupKind) -> Result<HerdGroup, LivestockError> {
+        let name = self.validate_group_name(Some(id), &name)?;
+        let group = self.get_group_mut(id)?;
+        let before = group.clone();
+        group.name = name;
+        group.kind = kind;
+        let updated = group.clone();
+
+        self.log_event(caller, None, LivestockEvent::GroupUpdated { before, after: updated.clone() });
+        Ok(updated)
+    }
+
+    // function to add animals to a group and remove others from it
+    fn change_group_members(&mut self, caller: Principal, id: u64, add: Vec<u64>, remove: Vec<u64>) -> Result<HerdGroup, LivestockError> {
+        let add = self.validate_members(add)?;
+        let group = self.get_group_mut(id)?;
+        let before = group.clone();
+        group.member_ids.retain(|member_id| !remove.contains(member_id));
+        group.member_ids.extend(add);
+        group.member_ids.sort();
+        group.member_ids.dedup();
+        let updated = group.clone();
+
+        self.log_event(caller, None, LivestockEvent::GroupUpdated { before, after: updated.clone() });
+        Ok(updated)
+    }
+
+    // function to delete a group, its animals are left as they are
+    fn delete_group(&mut self, caller: Principal, id: u64) -> Result<HerdGroup, LivestockError> {
+        let group = self.groups.remove(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a group with id={} not found", id),
+        })?;
+
+        self.log_event(caller, None, LivestockEvent::GroupDeleted { group: group.clone() });
+        Ok(group)
+    }
+
+    // Looks up a group by ID
+    fn get_group(&self, id: u64) -> Result<&HerdGroup, LivestockError> {
+        self.groups.get(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a group with id={} not found", id),
+        })
+    }
+
+    // Mutable lookup of a group by ID
+    fn get_group_mut(&mut self, id: u64) -> Result<&mut HerdGroup, LivestockError> {
+        self.groups.get_mut(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a group with id={} not found", id),
+        })
+    }
+
+    // Group names are trimmed and unique, ignoring case
+    fn validate_group_name(&self, id: Option<u64>, name: &str) -> Result<String, LivestockError> {
+        let name = name.trim().to_string();
+        if name.is_empty() || name.chars().count() > MAX_GROUP_NAME_LENGTH {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("group name has to be between 1 and {} characters long", MAX_GROUP_NAME_LENGTH),
+            });
+        }
+        if let Some(other) = self.groups.values().find(|group| Some(group.id) != id && group.name.to_lowercase() == name.to_lowercase()) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("group with id={} is already called {}", other.id, other.name),
+            });
+        }
+        Ok(name)
+    }
+
+    // Group members have to exist, they are sorted and deduplicated
+    fn validate_members(&self, mut member_ids: Vec<u64>) -> Result<Vec<u64>, LivestockError> {
+        for id in &member_ids {
+            self.get_animal(*id)?;
+        }
+        member_ids.sort();
+        member_ids.dedup();
+        Ok(member_ids)
+    }
+
+    // The animals a batch call acts on, without duplicates
+    fn batch_animal_ids(&self, target: BatchTarget) -> Result<Vec<u64>, LivestockError> {
+        let mut ids = match target {
+            BatchTarget::Group(group_id) => {
+                let group = self.get_group(group_id)?;
+                group.member_ids.iter().copied().filter(|id| self.animal.get(id).is_some_and(|animal| animal.in_herd())).collect()
+            }
+            BatchTarget::Animals(ids) => ids,
+        };
+        ids.sort();
+        ids.dedup();
+        if ids.len() > MAX_BATCH_SIZE {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("a batch call can act on at most {} animals", MAX_BATCH_SIZE),
+            });
+        }
+        Ok(ids)
+    }
+
+    // Applies an operation to every animal of a batch. A failure for one animal is reported and the rest carry on.
+    fn run_batch(&mut self, target: BatchTarget, mut operation: impl FnMut(&mut Self, u64) -> Result<(), LivestockError>) -> Result<Vec<BatchOutcome>, LivestockError> {
+        let ids = self.batch_animal_ids(target)?;
+        Ok(ids
+            .into_iter()
+            .map(|animal_id| BatchOutcome {
+                animal_id,
+                error: operation(self, animal_id).err(),
+            })
+            .collect())
+    }
+
+    // function to record a dose of the same medication given to every animal of a batch
+    fn batch_track_medication(&mut self, caller: Principal, target: BatchTarget, medication_name: String, dosage: String) -> Result<Vec<BatchOutcome>, LivestockError> {
+        self.run_batch(target, |system, id| {
+            system.track_medication(caller, id, medication_name.clone(), dosage.clone()).map(|_| ())
+        })
+    }
+
+    // function to change the health status of every animal of a batch
+    fn batch_update_health_status(&mut self, caller: Principal, target: BatchTarget, new_status: HealthStatus, note: String) -> Result<Vec<BatchOutcome>, LivestockError> {
+        self.run_batch(target, |system, id| {
+            system.update_health_status(caller, id, new_status, note.clone()).map(|_| ())
+        })
+    }
+
+    // function to move every animal of a batch to a location while it has room
+    fn batch_move_animals(&mut self, caller: Principal, target: BatchTarget, to: Option<u64>) -> Result<Vec<BatchOutcome>, LivestockError> {
+        self.run_batch(target, |system, id| {
+            system.move_animals(caller, vec![id], to).map(|_| ())
+        })
+    }
+
+    // function to add a feed to the inventory, it starts out of stock
+    fn add_feed_item(&mut self, caller: Principal, payload: FeedItemPayload) -> Result<FeedItem, LivestockError> {
+        validate_feed_item(&payload)?;
+        let item = FeedItem {
+            id: self.next_feed_id,
+            name: payload.name.trim().to_string(),
+            nutrients: payload.nutrients,
+            cost_per_kg: payload.cost_per_kg,
+            stock_kg: 0.0,
+        };
+        self.feed_items.insert(item.id, item.clone());
+        self.next_feed_id += 1;
+
+        self.log_event(caller, None, LivestockEvent::FeedItemAdded { item: item.clone() });
+        Ok(item)
+    }
+
+    // function to edit the name, nutrients or price of a feed
+    fn update_feed_item(&mut self, caller: Principal, id: u64, payload: FeedItemPayload) -> Result<FeedItem, LivestockError> {
+        validate_feed_item(&payload)?;
+        let item = self.get_feed_item_mut(id)?;
+        let before = item.clone();
+        item.name = payload.name.trim().to_string();
+        item.nutrients = payload.nutrients;
+        item.cost_per_kg = payload.cost_per_kg;
+        let updated = item.clone();
+
+        self.log_event(caller, None, LivestockEvent::FeedItemUpdated { before, after: updated.clone() });
+        Ok(updated)
+    }
+
+    // function to add a purchase of a feed to its stock
+    fn restock_feed(&mut self, caller: Principal, id: u64, quantity_kg: f64) -> Result<FeedItem, LivestockError> {
+        if !quantity_kg.is_finite() || quantity_kg <= 0.0 {
+            return Err(LivestockError::InvalidInput {
+                msg: "quantity must be a positive number of kg".to_string(),
+            });
+        }
+        let item = self.get_feed_item_mut(id)?;
+        let before_kg = item.stock_kg;
+        item.stock_kg += quantity_kg;
+        let updated = item.clone();
+
+        self.log_event(caller, None, LivestockEvent::FeedRestocked { feed_id: id, before_kg, after_kg: updated.stock_kg });
+        Ok(updated)
+    }
+
+    // Mutable lookup of a feed by ID
+    fn get_feed_item_mut(&mut self, id: u64) -> Result<&mut FeedItem, LivestockError> {
+        self.feed_items.get_mut(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a feed item with id={} not found", id),
+        })
+    }
+
+    // function to put a group on a new ration, ending the plan it was on
+    fn set_ration_plan(&mut self, caller: Principal, group_id: u64, components: Vec<RationComponent>) -> Result<RationPlan, LivestockError> {
+        self.get_group(group_id)?;
+        if components.is_empty() || components.len() > MAX_RATION_COMPONENTS {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("a ration has between 1 and {} feeds", MAX_RATION_COMPONENTS),
+            });
+        }
+        for (index, component) in components.iter().enumerate() {
+            if !self.feed_items.contains_key(&component.feed_id) {
+                return Err(LivestockError::NotFound {
+                    msg: format!("a feed item with id={} not found", component.feed_id),
+                });
+            }
+            if !component.kg_per_head_per_day.is_finite() || component.kg_per_head_per_day <= 0.0 {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("feed with id={} needs a positive daily quantity", component.feed_id),
+                });
+            }
+            if components[..index].iter().any(|other| other.feed_id == component.feed_id) {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("feed with id={} is listed twice", component.feed_id),
+                });
+            }
+        }
+
+        // An ended plan has nothing to end, so a failure here cannot happen once the group is known
+        let _ = self.end_ration_plan(caller, group_id);
+        let plan = RationPlan {
+            id: self.next_ration_id,
+            group_id,
+            components,
+            start_date: time(),
+            end_date: None,
+            set_by: caller,
+        };
+        self.ration_plans.push(plan.clone());
+        self.next_ration_id += 1;
+
+        self.log_event(caller, None, LivestockEvent::RationPlanStarted { plan: plan.clone() });
+        Ok(plan)
+    }
+
+    // function to take a group off its ration
+    fn end_ration_plan(&mut self, caller: Principal, group_id: u64) -> Result<RationPlan, LivestockError> {
+        let plan = self
+            .ration_plans
+            .iter_mut()
+            .find(|plan| plan.group_id == group_id && plan.end_date.is_none())
+            .ok_or_else(|| LivestockError::NotFound {
+                msg: format!("group with id={} is not on a ration", group_id),
+            })?;
+        plan.end_date = Some(time());
+        let ended = plan.clone();
+
+        self.log_event(caller, None, LivestockEvent::RationPlanEnded { plan_id: ended.id, group_id });
+        Ok(ended)
+    }
+
+    // Looks up a ration plan by ID
+    fn get_ration_plan(&self, id: u64) -> Result<&RationPlan, LivestockError> {
+        self.ration_plans.iter().find(|plan| plan.id == id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a ration plan with id={} not found", id),
+        })
+    }
+
+    // function to record feed handed out to a group or an animal, taken from the stock
+    fn record_feed_delivery(&mut self, caller: Principal, payload: FeedDeliveryPayload) -> Result<FeedDelivery, LivestockError> {
+        let FeedDeliveryPayload { feed_id, quantity_kg, date, target } = payload;
+        if !quantity_kg.is_finite() || quantity_kg <= 0.0 {
+            return Err(LivestockError::InvalidInput {
+                msg: "quantity must be a positive number of kg".to_string(),
+            });
+        }
+        if date > time() {
+            return Err(LivestockError::InvalidInput {
+                msg: "delivery date cannot be in the future".to_string(),
+            });
+        }
+        let (animal_ids, ration_id) = match target {
+            FeedTarget::Group(group_id) => {
+                let group = self.get_group(group_id)?;
+                let members: Vec<u64> = group.member_ids.iter().copied().filter(|id| self.animal.get(id).is_some_and(|animal| animal.in_herd())).collect();
+                let ration_id = self
+                    .ration_plans
+                    .iter()
+                    .find(|plan| plan.group_id == group_id && plan.start_date <= date && !matches!(plan.end_date, Some(end) if end <= date))
+                    .map(|plan| plan.id);
+                (members, ration_id)
+            }
+            FeedTarget::Animal(animal_id) => {
+                let animal = self.get_animal(animal_id)?;
+                (if animal.in_herd() { vec![animal_id] } else { Vec::new() }, None)
+            }
+        };
+        if animal_ids.is_empty() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("{:?} has no animals in the herd to feed", target),
+            });
+        }
+        let item = self.get_feed_item_mut(feed_id)?;
+        if quantity_kg > item.stock_kg {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("only {} kg of {} left in stock", item.stock_kg, item.name),
+            });
+        }
+        item.stock_kg -= quantity_kg;
+        let cost = quantity_kg * item.cost_per_kg;
+
+        let delivery = FeedDelivery {
+            id: self.next_feed_delivery_id,
+            feed_id,
+            quantity_kg,
+            cost,
+            date,
+            target,
+            animal_ids,
+            ration_id,
+            recorded_by: caller,
+        };
+        self.feed_deliveries.push(delivery.clone());
+        self.next_feed_delivery_id += 1;
+
+        let animal_id = match target {
+            FeedTarget::Animal(animal_id) => Some(animal_id),
+            FeedTarget::Group(_) => None,
+        };
+        self.log_event(caller, animal_id, LivestockEvent::FeedDelivered { delivery: delivery.clone() });
+        Ok(delivery)
+    }
+
+    // Feed deliveries to a group, or shared by an animal, within a date range, oldest first
+    fn find_feed_deliveries(&self, target: Option<FeedTarget>, from: Option<u64>, to: Option<u64>) -> Vec<&FeedDelivery> {
+        let mut deliveries: Vec<&FeedDelivery> = self
+            .feed_deliveries
+            .iter()
+            .filter(|delivery| {
+                let for_target = match target {
+                    Some(FeedTarget::Group(group_id)) => delivery.target == FeedTarget::Group(group_id),
+                    Some(FeedTarget::Animal(animal_id)) => delivery.animal_ids.contains(&animal_id),
+                    None => true,
+                };
+                for_target && !matches!(from, Some(from) if delivery.date < from) && !matches!(to, Some(to) if delivery.date > to)
+            })
+            .collect();
+        deliveries.sort_by_key(|delivery| (delivery.date, delivery.id));
+        deliveries
+    }
+
+    // function to report the feed eaten and its cost against the weight gained, by a group or an animal over a date range
+    fn feed_report(&self, target: FeedTarget, from: Option<u64>, to: Option<u64>) -> Result<FeedReport, LivestockError> {
+        let animal_id = match target {
+            FeedTarget::Group(group_id) => {
+                self.get_group(group_id)?;
+                None
+            }
+            FeedTarget::Animal(animal_id) => {
+                self.get_animal(animal_id)?;
+                Some(animal_id)
+            }
+        };
+        let deliveries = self.find_feed_deliveries(Some(target), from, to);
+        Ok(self.summarize_feed(&deliveries, animal_id, from, to))
+    }
+
+    // function to report the feed eaten on a ration plan and its cost against the weight gained
+    fn ration_report(&self, ration_id: u64) -> Result<FeedReport, LivestockError> {
+        let plan = self.get_ration_plan(ration_id)?;
+        let deliveries: Vec<&FeedDelivery> = self.feed_deliveries.iter().filter(|delivery| delivery.ration_id == Some(ration_id)).collect();
+        Ok(self.summarize_feed(&deliveries, None, Some(plan.start_date), plan.end_date))
+    }
+
+    // Splits each delivery evenly over the animals that shared it, optionally counting one animal only. Weight gain
+    // is measured between the first and last weighing of each animal within the range, or the span of the deliveries.
+    fn summarize_feed(&self, deliveries: &[&FeedDelivery], only_animal: Option<u64>, from: Option<u64>, to: Option<u64>) -> FeedReport {
+        let mut fed: HashMap<u64, f64> = HashMap::new();
+        let mut feed_kg = 0.0;
+        let mut cost = 0.0;
+        for delivery in deliveries {
+            let share = 1.0 / delivery.animal_ids.len() as f64;
+            for animal_id in delivery.animal_ids.iter().filter(|id| !matches!(only_animal, Some(only) if **id != only)) {
+                *fed.entry(*animal_id).or_insert(0.0) += delivery.quantity_kg * share;
+                feed_kg += delivery.quantity_kg * share;
+                cost += delivery.cost * share;
+            }
+        }
+
+        let from = from.or_else(|| deliveries.iter().map(|delivery| delivery.date).min());
+        let to = to.or_else(|| deliveries.iter().map(|delivery| delivery.date).max());
+        let mut weighed_head_count = 0;
+        let mut weighed_feed_kg = 0.0;
+        let mut weight_gain_kg = 0.0;
+        for (animal_id, kg) in &fed {
+            if let Some(gain) = self.weight_gain(*animal_id, from, to) {
+                weighed_head_count += 1;
+                weighed_feed_kg += kg;
+                weight_gain_kg += gain;
+            }
+        }
+
+        let head_count = fed.len() as u64;
+        FeedReport {
+            head_count,
+            feed_kg,
+            cost,
+            cost_per_head: if head_count > 0 { cost / head_count as f64 } else { 0.0 },
+            weighed_head_count,
+            weight_gain_kg,
+            feed_conversion_ratio: if weight_gain_kg > 0.0 { Some(weighed_feed_kg / weight_gain_kg) } else { None },
+        }
+    }
+
+    // Weight in kg an animal put on between its first and last weighing in a date range
+    fn weight_gain(&self, id: u64, from: Option<u64>, to: Option<u64>) -> Option<f64> {
+        let weighings: Vec<(u64, f32)> = self
+            .get_measurements(id, from, to)
+            .ok()?
+            .into_iter()
+            .filter_map(|m| m.weight.map(|weight| (m.measured_at, weight)))
+            .collect();
+        match (weighings.first(), weighings.last()) {
+            (Some(&(first_at, first)), Some(&(last_at, last))) if last_at > first_at => Some(last as f64 - first as f64),
+            _ => None,
+        }
+    }
+
+    // function to list one page of the animals matching a filter, in the requested order
+    fn list_animals(&self, filter: &AnimalFilter, sort: AnimalSort, cursor: Option<AnimalCursor>, limit: Option<u32>, now: u64) -> Result<AnimalPage, LivestockError> {
+        let limit = page_size(limit)?;
+        let breed = filter.breed.as_ref().map(|breed| breed.trim().to_lowercase());
+        let tags: Vec<String> = filter.tags.iter().map(|tag| tag.trim().to_lowercase()).collect();
+
+        let mut matches: Vec<(AnimalCursor, &Livestock)> = self
+            .animal
+            .values()
+            .filter(|animal| {
+                let age_days = animal.age_at(now).days;
+                (animal.in_herd() || filter.include_disposed == Some(true))
+                    && !matches!(&breed, Some(breed) if animal.breed.trim().to_lowercase() != *breed)
+                    && !matches!(filter.health_status, Some(status) if animal.healthstatus != status)
+                    && !matches!(filter.min_age_days, Some(min) if age_days < min)
+                    && !matches!(filter.max_age_days, Some(max) if age_days > max)
+                    && !matches!(filter.sex, Some(sex) if animal.sex != sex)
+                    && tags.iter().all(|tag| animal.tags.contains(tag))
+                    && !matches!(filter.location_id, Some(location_id) if !self.is_within(animal.location_id, location_id))
+            })
+            .map(|animal| {
+                let sort_value = match sort.key {
+                    AnimalSortKey::Id => animal.id,
+                    AnimalSortKey::Age => animal.date_of_birth,
+                    AnimalSortKey::UpdatedAt => animal.updated_at.unwrap_or(animal.created_at),
+                };
+                (AnimalCursor { sort_value, id: animal.id }, animal)
+            })
+            .collect();
+
+        // The oldest animal has the earliest date of birth, so ascending age is descending birth date
+        let descending = sort.descending != (sort.key == AnimalSortKey::Age);
+        matches.sort_by_key(|(key, _)| (key.sort_value, key.id));
+        if descending {
+            matches.reverse();
+        }
+
+        Ok(animal_page(matches, descending, cursor, limit))
+    }
+
+    // function to record the reproductive state of a female animal
+    fn update_reproductive_status(&mut self, caller: Principal, id: u64, status: ReproductiveStatus) -> Result<Livestock, LivestockError> {
+        let animal = self.get_animal_mut(id)?;
+        if status != ReproductiveStatus::Open && animal.sex != Sex::Female {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is not female", id),
+            });
+        }
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", id),
+            });
+        }
+        let before = std::mem::replace(&mut animal.reproductive_status, status);
+        animal.updated_at = Some(time());
+        let updated = animal.clone();
+
+        self.log_event(caller, Some(id), LivestockEvent::ReproductiveStatusUpdated { before, after: status });
+
+        Ok(updated)
+    }
+
+    // function to record an animal leaving the herd, the record is kept for pedigrees and reporting.
+    // Animals sold or culled can enter the food chain, so their meat must not be under withdrawal.
+    fn dispose_animal(&mut self, caller: Principal, id: u64, payload: DispositionPayload) -> Result<Livestock, LivestockError> {
+        let DispositionPayload { kind, date, reason, buyer } = payload;
+        let now = time();
+        if date > now {
+            return Err(LivestockError::InvalidInput {
+                msg: "disposition date cannot be in the future".to_string(),
+            });
+        }
+        if reason.chars().count() > MAX_NOTE_LENGTH {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("reason cannot be longer than {} characters", MAX_NOTE_LENGTH),
+            });
+        }
+        let buyer = buyer.map(|buyer| buyer.trim().to_string()).filter(|buyer| !buyer.is_empty());
+        if buyer.is_none() && matches!(kind, DispositionKind::Sold | DispositionKind::Transferred) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("a {:?} disposition needs a buyer", kind),
+            });
+        }
+        let animal = self.get_animal(id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} has already left the herd", id),
+            });
+        }
+        if date < animal.date_of_birth {
+            return Err(LivestockError::InvalidInput {
+                msg: "disposition date cannot be before the date of birth".to_string(),
+            });
+        }
+        if matches!(kind, DispositionKind::Sold | DispositionKind::Culled) {
+            let withdrawal = self.withdrawal(animal);
+            if withdrawal.meat_withheld(date) {
+                return Err(LivestockError::UnderWithdrawal {
+                    msg: format!("meat of animal with id={} is under withdrawal until {}", id, withdrawal.meat_safe_after.unwrap_or_default()),
+                });
+            }
+        }
+
+        let disposition = Disposition {
+            kind,
+            date,
+            reason,
+            buyer,
+            recorded_by: caller,
+        };
+        let animal = self.get_animal_mut(id)?;
+        animal.disposition = Some(disposition.clone());
+        animal.reproductive_status = ReproductiveStatus::Open;
+        animal.updated_at = Some(now);
+        let updated = animal.clone();
+        self.log_event(caller, Some(id), LivestockEvent::AnimalDisposed { disposition });
+
+        // Nobody is left to treat an animal that left the herd
+        let open: Vec<u64> = self.health_alerts.iter().filter(|alert| alert.animal_id == id && alert.is_open()).map(|alert| alert.id).collect();
+        for alert_id in open {
+            self.resolve_alert(caller, alert_id)?;
+        }
+
+        Ok(updated)
+    }
+
+    // One page of the animals that left the herd, optionally of one kind and within a date range, by disposition date
+    fn disposed_animals(&self, kind: Option<DispositionKind>, from: Option<u64>, to: Option<u64>, cursor: Option<AnimalCursor>, limit: Option<u32>) -> Result<AnimalPage, LivestockError> {
+        let limit = page_size(limit)?;
+        let mut matches: Vec<(AnimalCursor, &Livestock)> = self
+            .animal
+            .values()
+            .filter_map(|animal| {
+                let disposition = animal.disposition.as_ref()?;
+                let matching = !matches!(kind, Some(kind) if disposition.kind != kind)
+                    && !matches!(from, Some(from) if disposition.date < from)
+                    && !matches!(to, Some(to) if disposition.date > to);
+                matching.then_some((AnimalCursor { sort_value: disposition.date, id: animal.id }, animal))
+            })
+            .collect();
+        matches.sort_by_key(|(key, _)| (key.sort_value, key.id));
+        Ok(animal_page(matches, false, cursor, limit))
+    }
+
+    // function to record a sale of milk from an animal, refused while its milk is under withdrawal
+    fn record_milk_sale(&mut self, caller: Principal, id: u64, litres: f64, buyer: String) -> Result<(), LivestockError> {
+        if !litres.is_finite() || litres <= 0.0 {
+            return Err(LivestockError::InvalidInput {
+                msg: "litres sold must be a positive number".to_string(),
+            });
+        }
+        let buyer = buyer.trim().to_string();
+        if buyer.is_empty() {
+            return Err(LivestockError::InvalidInput {
+                msg: "buyer cannot be empty".to_string(),
+            });
+        }
+        let animal = self.get_animal(id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", id),
+            });
+        }
+        let withdrawal = self.withdrawal(animal);
+        if withdrawal.milk_withheld(time()) {
+            return Err(LivestockError::UnderWithdrawal {
+                msg: format!("milk of animal with id={} is under withdrawal until {}", id, withdrawal.milk_safe_after.unwrap_or_default()),
+            });
+        }
+        self.log_event(caller, Some(id), LivestockEvent::MilkSold { litres, buyer });
+        Ok(())
+    }
+
+    // function to record a milking of a dairy animal. Milk taken under withdrawal is recorded as withheld,
+    // and a somatic cell count at or above the mastitis threshold raises an alert.
+    fn record_milking(&mut self, caller: Principal, id: u64, payload: MilkRecordPayload) -> Result<MilkRecord, LivestockError> {
+        let MilkRecordPayload { milked_at, volume_litres, fat_percent, protein_percent, somatic_cell_count } = payload;
+        if !volume_litres.is_finite() || volume_litres <= 0.0 || volume_litres > MAX_MILKING_LITRES {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("a milking yields more than 0 and at most {} litres", MAX_MILKING_LITRES),
+            });
+        }
+        for (name, value, max) in [("fat", fat_percent, MAX_MILK_FAT_PERCENT), ("protein", protein_percent, MAX_MILK_PROTEIN_PERCENT)] {
+            if matches!(value, Some(value) if !(0.0..=max).contains(&value)) {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("{} has to be between 0 and {} percent", name, max),
+                });
+            }
+        }
+        let now = time();
+        if milked_at > now {
+            return Err(LivestockError::InvalidInput {
+                msg: "milking time cannot be in the future".to_string(),
+            });
+        }
+
+        let animal = self.get_animal(id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", id),
+            });
+        }
+        if animal.sex != Sex::Female {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is not female", id),
+            });
+        }
+        if milked_at < animal.date_of_birth {
+            return Err(LivestockError::InvalidInput {
+                msg: "milking time cannot be before the animal was born".to_string(),
+            });
+        }
+        if animal.milk_records.iter().any(|record| record.milked_at == milked_at) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} already has a milking recorded at {}", id, milked_at),
+            });
+        }
+        let record = MilkRecord {
+            id: self.next_milk_record_id,
+            milked_at,
+            volume_litres,
+            fat_percent,
+            protein_percent,
+            somatic_cell_count,
+            withheld: self.withdrawal(animal).milk_withheld(milked_at),
+            recorded_by: caller,
+        };
+        self.next_milk_record_id += 1;
+
+        let animal = self.get_animal_mut(id)?;
+        // keep the history sorted by time, late entries go in their place
+        let position = animal.milk_records.partition_point(|m| m.milked_at <= milked_at);
+        animal.milk_records.insert(position, record.clone());
+        animal.updated_at = Some(now);
+
+        self.log_event(caller, Some(id), LivestockEvent::MilkingRecorded { record: record.clone() });
+        self.raise_mastitis_alert(id, &record, now);
+        Ok(record)
+    }
+
+    // Raises a mastitis alert for a milking with a somatic cell count at or above the threshold, unless the
+    // animal already has one open. Twice the threshold makes it a high severity alert. Returns the alert ID.
+    fn raise_mastitis_alert(&mut self, animal_id: u64, record: &MilkRecord, now: u64) -> Option<u64> {
+        let cell_count = record.somatic_cell_count.filter(|count| *count >= self.mastitis_cell_count)?;
+        if let Some(alert_id) = self.mastitis_alerts.get(&animal_id) {
+            if self.health_alerts.iter().any(|alert| alert.id == *alert_id && alert.is_open()) {
+                return None;
+            }
+        }
+        let severity = if cell_count / 2 >= self.mastitis_cell_count { AlertSeverity::High } else { AlertSeverity::Medium };
+        let message = format!("Possible mastitis: somatic cell count of {} cells/ml at milking {}", cell_count, record.id);
+        let alert_id = self.raise_alert(animal_id, AlertKind::Mastitis, severity, message, now);
+        self.mastitis_alerts.insert(animal_id, alert_id);
+        Some(alert_id)
+    }
+
+    // function to change the somatic cell count from which a milking raises a mastitis alert
+    fn set_mastitis_threshold(&mut self, caller: Principal, cell_count: u32) -> Result<u32, LivestockError> {
+        if !(MIN_MASTITIS_CELL_COUNT..=MAX_MASTITIS_CELL_COUNT).contains(&cell_count) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("mastitis threshold has to be between {} and {} cells/ml", MIN_MASTITIS_CELL_COUNT, MAX_MASTITIS_CELL_COUNT),
+            });
+        }
+        let before = self.mastitis_cell_count;
+        self.mastitis_cell_count = cell_count;
+        self.log_event(caller, None, LivestockEvent::MastitisThresholdChanged { before, after: cell_count });
+        Ok(cell_count)
+    }
+
+    // function to get the milkings of an animal within a date range
+    fn get_milk_records(&self, id: u64, from: Option<u64>, to: Option<u64>) -> Result<Vec<MilkRecord>, LivestockError> {
+        let animal = self.get_animal(id)?;
+        Ok(animal
+            .milk_records
+            .iter()
+            .filter(|m| m.milked_at >= from.unwrap_or(0) && m.milked_at <= to.unwrap_or(u64::MAX))
+            .cloned()
+            .collect())
+    }
+
+    // function to get the lactation curve of an animal since a calving, its latest one by default
+    fn lactation_curve(&self, id: u64, calving_date: Option<u64>, now: u64) -> Result<LactationCurve, LivestockError> {
+        let animal = self.get_animal(id)?;
+        let calvings = calvings(animal);
+        let index = match calving_date {
+            Some(date) => calvings.iter().position(|calving| *calving == date).ok_or_else(|| LivestockError::NotFound {
+                msg: format!("animal with id={} did not calve at {}", id, date),
+            })?,
+            None => calvings.len().checked_sub(1).ok_or_else(|| LivestockError::NotFound {
+                msg: format!("animal with id={} has not calved", id),
+            })?,
+        };
+        let calving = calvings[index];
+        let next_calving = calvings.get(index + 1).copied().unwrap_or(u64::MAX);
+
+        let milkings: Vec<&MilkRecord> = animal.milk_records.iter().filter(|m| m.milked_at >= calving && m.milked_at < next_calving).collect();
+        let mut daily: BTreeMap<u64, f64> = BTreeMap::new();
+        for milking in &milkings {
+            *daily.entry((milking.milked_at - calving) / NANOS_PER_DAY).or_insert(0.0) += milking.volume_litres;
+        }
+        let (peak_day, peak_litres) = daily.iter().fold((None, 0.0), |peak, (day, litres)| if *litres > peak.1 { (Some(*day), *litres) } else { peak });
+        let days_in_milk = if animal.reproductive_status == (ReproductiveStatus::Lactating { since: calving }) {
+            now.saturating_sub(calving) / NANOS_PER_DAY
+        } else {
+            daily.keys().next_back().copied().unwrap_or(0)
+        };
+
+        Ok(LactationCurve {
+            animal_id: id,
+            calving_date: calving,
+            days_in_milk,
+            milkings: milkings.len() as u64,
+            total_litres: daily.values().sum(),
+            yield_305_days: daily.range(..STANDARD_LACTATION_DAYS).map(|(_, litres)| litres).sum(),
+            peak_litres,
+            peak_day,
+            daily_yields: daily.into_iter().map(|(day_in_milk, litres)| DailyYield { day_in_milk, litres }).collect(),
+        })
+    }
+
+    // function to total the milk of the whole herd per day within a date range, animals that left the herd included
+    fn herd_milk_totals(&self, from: Option<u64>, to: Option<u64>) -> Vec<DailyMilkTotal> {
+        let mut days: BTreeMap<u64, DailyMilkTotal> = BTreeMap::new();
+        let mut milked: HashSet<(u64, u64)> = HashSet::new();
+        // sampled volume and volume weighted sum of the fat, protein and cell count per day
+        let mut samples: HashMap<u64, [(f64, f64); 3]> = HashMap::new();
+        for animal in self.animal.values() {
+            for milking in animal.milk_records.iter().filter(|m| m.milked_at >= from.unwrap_or(0) && m.milked_at <= to.unwrap_or(u64::MAX)) {
+                let day = milking.milked_at - milking.milked_at % NANOS_PER_DAY;
+                let total = days.entry(day).or_insert_with(|| DailyMilkTotal {
+                    day,
+                    animals: 0,
+                    milkings: 0,
+                    litres: 0.0,
+                    withheld_litres: 0.0,
+                    fat_percent: None,
+                    protein_percent: None,
+                    somatic_cell_count: None,
+                });
+                if milked.insert((day, animal.id)) {
+                    total.animals += 1;
+                }
+                total.milkings += 1;
+                total.litres += milking.volume_litres;
+                if milking.withheld {
+                    total.withheld_litres += milking.volume_litres;
+                }
+                let values = [milking.fat_percent.map(f64::from), milking.protein_percent.map(f64::from), milking.somatic_cell_count.map(f64::from)];
+                for (sample, value) in samples.entry(day).or_default().iter_mut().zip(values) {
+                    if let Some(value) = value {
+                        sample.0 += milking.volume_litres;
+                        sample.1 += value * milking.volume_litres;
+                    }
+                }
+            }
+        }
+        days.into_values()
+            .map(|mut total| {
+                let average = |(volume, sum): (f64, f64)| if volume > 0.0 { Some(sum / volume) } else { None };
+                let [fat, protein, cells] = samples[&total.day].map(average);
+                total.fat_percent = fat;
+                total.protein_percent = protein;
+                total.somatic_cell_count = cells;
+                total
+            })
+            .collect()
+    }
+
+    // function to record a weight, height or body condition measurement of an animal
+    fn record_measurement(&mut self, caller: Principal, id: u64, payload: MeasurementPayload) -> Result<Measurement, LivestockError> {
+        let MeasurementPayload { measured_at, weight, height, body_condition_score } = payload;
+        if weight.is_none() && height.is_none() && body_condition_score.is_none() {
+            return Err(LivestockError::InvalidInput {
+                msg: "a measurement needs a weight, height or body condition score".to_string(),
+            });
+        }
+        if measured_at > time() {
+            return Err(LivestockError::InvalidInput {
+                msg: "measurement date cannot be in the future".to_string(),
+            });
+        }
+        for (name, value) in [("weight", weight), ("height", height)] {
+            if let Some(value) = value {
+                if !value.is_finite() || value <= 0.0 {
+                    return Err(LivestockError::InvalidInput {
+                        msg: format!("{} must be a positive number", name),
+                    });
+                }
+            }
+        }
+        if let Some(score) = body_condition_score {
+            if !(1.0..=9.0).contains(&score) {
+                return Err(LivestockError::InvalidInput {
+                    msg: "body condition score must be between 1 and 9".to_string(),
+                });
+            }
+        }
+
+        let animal = self.get_animal_mut(id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", id),
+            });
+        }
+        let measurement = Measurement {
+            measured_at,
+            weight,
+            height,
+            body_condition_score,
+            recorded_by: Some(caller),
+        };
+        // keep the history sorted by date, late entries go in their place
+        let position = animal.measurements.partition_point(|m| m.measured_at <= measured_at);
+        animal.measurements.insert(position, measurement.clone());
+        // the latest height stays on the animal record
+        if let Some(latest) = animal.measurements.iter().rev().find_map(|m| m.height) {
+            animal.height = latest;
+        }
+        animal.updated_at = Some(time());
+
+        self.log_event(caller, Some(id), LivestockEvent::MeasurementRecorded { measurement: measurement.clone() });
+
+        Ok(measurement)
+    }
+
+    // function to get the measurements of an animal taken within a date range
+    fn get_measurements(&self, id: u64, from: Option<u64>, to: Option<u64>) -> Result<Vec<Measurement>, LivestockError> {
+        let animal = self.get_animal(id)?;
+        Ok(animal
+            .measurements
+            .iter()
+            .filter(|m| m.measured_at >= from.unwrap_or(0) && m.measured_at <= to.unwrap_or(u64::MAX))
+            .cloned()
+            .collect())
+    }
+
+    // function to get the average daily gain in kg of an animal between the first and last weighing in a date range
+    fn average_daily_gain(&self, id: u64, from: Option<u64>, to: Option<u64>) -> Result<f64, LivestockError> {
+        let weighings: Vec<(u64, f32)> = self
+            .get_measurements(id, from, to)?
+            .into_iter()
+            .filter_map(|m| m.weight.map(|weight| (m.measured_at, weight)))
+            .collect();
+        match (weighings.first(), weighings.last()) {
+            (Some(&(first_at, first)), Some(&(last_at, last))) if last_at > first_at => {
+                let days = (last_at - first_at) as f64 / NANOS_PER_DAY as f64;
+                Ok((last as f64 - first as f64) / days)
+            }
+            _ => Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} needs weighings on two different dates in the range", id),
+            }),
+        }
+    }
+
+    // function to set the minimum average daily gain expected of a breed, None removes it
+    fn set_growth_benchmark(&mut self, caller: Principal, breed: String, min_daily_gain: Option<f64>) -> Result<(), LivestockError> {
+        let key = breed.trim().to_lowercase();
+        if key.is_empty() {
+            return Err(LivestockError::InvalidInput {
+                msg: "breed cannot be empty".to_string(),
+            });
+        }
+        let before = match min_daily_gain {
+            Some(gain) if !gain.is_finite() => {
+                return Err(LivestockError::InvalidInput {
+                    msg: "benchmark must be a finite number".to_string(),
+                });
+            }
+            Some(gain) => self.growth_benchmarks.insert(key, gain),
+            None => {
+                let removed = self.growth_benchmarks.remove(&key);
+                if removed.is_none() {
+                    return Err(LivestockError::NotFound {
+                        msg: format!("no growth benchmark for breed {}", breed),
+                    });
+                }
+                removed
+            }
+        };
+
+        self.log_event(caller, None, LivestockEvent::GrowthBenchmarkUpdated { breed, before, after: min_daily_gain });
+
+        Ok(())
+    }
+
+    // function to list the animals in the herd growing slower than their breed benchmark over a date range
+    fn growth_below_benchmark(&self, from: Option<u64>, to: Option<u64>) -> Vec<GrowthReport> {
+        let mut reports: Vec<GrowthReport> = self
+            .animal
+            .values()
+            .filter(|animal| animal.in_herd())
+            .filter_map(|animal| {
+                let benchmark = *self.growth_benchmarks.get(&animal.breed.trim().to_lowercase())?;
+                let average_daily_gain = self.average_daily_gain(animal.id, from, to).ok()?;
+                (average_daily_gain < benchmark).then(|| GrowthReport {
+                    animal_id: animal.id,
+                    breed: animal.breed.clone(),
+                    average_daily_gain,
+                    benchmark,
+                })
+            })
+            .collect();
+        reports.sort_by_key(|report| report.animal_id);
+        reports
+    }
+
+    // function to update the health status of an animal
+    fn update_health_status(&mut self, caller: Principal, id: u64, new_status: HealthStatus, note: String) -> Result<Livestock, LivestockError> {
+        if note.chars().count() > MAX_NOTE_LENGTH {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("note cannot be longer than {} characters", MAX_NOTE_LENGTH),
+            });
+        }
+        let transition = HealthTransition {
+            from: self.get_animal(id)?.healthstatus,
+            to: new_status,
+        };
+        if !self.health_transitions.contains(&transition) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} cannot go from {} to {}", id, transition.from, transition.to),
+            });
+        }
+
+        let now = time();
+        let animal = self.get_animal_mut(id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", id),
+            });
+        }
+        let before = std::mem::replace(&mut animal.healthstatus, new_status);
+        animal.status_history.push(StatusChange {
+            status: new_status,
+            changed_at: now,
+            changed_by: caller,
+            note: note.clone(),
+        });
+        animal.updated_at = Some(now);
+        let updated = animal.clone();
+
+        self.log_event(caller, Some(id), LivestockEvent::HealthStatusUpdated { before, after: new_status, note });
+
+        // Check if the new status is Critical or Sick and create an alert
+        let severity = match new_status {
+            HealthStatus::Critical => Some(AlertSeverity::Critical),
+            HealthStatus::Sick => Some(AlertSeverity::Medium),
+            HealthStatus::Healthy | HealthStatus::Recovering => None,
+        };
+        if let Some(severity) = severity {
+            self.raise_alert(id, AlertKind::StatusChange(new_status), severity, format!("Health status changed to {}", new_status), time());
+            ic_cdk::println!("ALERT: Animal with ID: {} is now {:?}", id, new_status);
+        }
+        // A healthy animal has no health problem left to alert about, other causes are resolved on their own
+        if new_status == HealthStatus::Healthy {
+            let open: Vec<u64> = self
+                .health_alerts
+                .iter()
+                .filter(|alert| alert.animal_id == id && alert.is_open() && matches!(alert.kind, AlertKind::StatusChange(HealthStatus::Sick | HealthStatus::Critical)))
+                .map(|alert| alert.id)
+                .collect();
+            for alert_id in open {
+                self.resolve_alert(caller, alert_id)?;
+            }
+        }
+
+        Ok(updated)
+    }
+
+    // function to replace the health status transitions update_health_status allows
+    fn set_health_transitions(&mut self, caller: Principal, transitions: Vec<HealthTransition>) -> Result<Vec<HealthTransition>, LivestockError> {
+        let mut allowed: Vec<HealthTransition> = Vec::new();
+        for transition in transitions {
+            if transition.from == transition.to {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("{} to {} is not a change of status", transition.from, transition.to),
+                });
+            }
+            if !allowed.contains(&transition) {
+                allowed.push(transition);
+            }
+        }
+        let before = std::mem::replace(&mut self.health_transitions, allowed.clone());
+        self.log_event(caller, None, LivestockEvent::HealthTransitionsChanged { before, after: allowed.clone() });
+        Ok(allowed)
+    }
+
+    // function to get the time in nanoseconds an animal spent in each health status, up to now or until it left the herd
+    fn time_in_status(&self, id: u64, now: u64) -> Result<HashMap<HealthStatus, u64>, LivestockError> {
+        let animal = self.get_animal(id)?;
+        let until = animal.left_herd_at().map_or(now, |date| date.min(now));
+        let mut durations: HashMap<HealthStatus, u64> = [HealthStatus::Healthy, HealthStatus::Sick, HealthStatus::Critical, HealthStatus::Recovering]
+            .into_iter()
+            .map(|status| (status, 0))
+            .collect();
+        for (index, change) in animal.status_history.iter().enumerate() {
+            let end = animal.status_history.get(index + 1).map_or(until, |next| next.changed_at.min(until));
+            *durations.entry(change.status).or_insert(0) += end.saturating_sub(change.changed_at);
+        }
+        Ok(durations)
+    }
+
+    // Checks a health record payload, the treating principal has to be a vet
+    fn validate_health_record(&self, payload: &HealthRecordPayload) -> Result<(), LivestockError> {
+        let diagnosis = payload.diagnosis.trim();
+        if diagnosis.is_empty() || diagnosis.chars().count() > MAX_DIAGNOSIS_LENGTH {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("diagnosis has to be between 1 and {} characters long", MAX_DIAGNOSIS_LENGTH),
+            });
+        }
+        if payload.symptoms.len() > MAX_SYMPTOMS || payload.symptoms.iter().any(|symptom| symptom.trim().is_empty()) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("a health record lists at most {} symptoms, none of them empty", MAX_SYMPTOMS),
+            });
+        }
+        if let Some(temperature) = payload.temperature {
+            if !(MIN_BODY_TEMPERATURE..=MAX_BODY_TEMPERATURE).contains(&temperature) {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("temperature has to be between {} and {} degrees Celsius", MIN_BODY_TEMPERATURE, MAX_BODY_TEMPERATURE),
+                });
+            }
+        }
+        if payload.lab_results.len() > MAX_LAB_RESULTS {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("a health record holds at most {} lab results", MAX_LAB_RESULTS),
+            });
+        }
+        for lab_result in &payload.lab_results {
+            if lab_result.test_name.trim().is_empty() {
+                return Err(LivestockError::InvalidInput {
+                    msg: "lab test name cannot be empty".to_string(),
+                });
+            }
+            if lab_result.reported_at > time() {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("lab result for {} is reported in the future", lab_result.test_name),
+                });
+            }
+        }
+        if payload.notes.chars().count() > MAX_NOTE_LENGTH {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("notes cannot be longer than {} characters", MAX_NOTE_LENGTH),
+            });
+        }
+        if self.roles.get(&payload.treating_vet) != Some(&Role::Vet) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("treating principal {} is not a vet", payload.treating_vet),
+            });
+        }
+        Ok(())
+    }
+
+    // function to add a health record to an animal
+    fn add_health_record(&mut self, caller: Principal, animal_id: u64, payload: HealthRecordPayload) -> Result<HealthRecord, LivestockError> {
+        self.validate_health_record(&payload)?;
+
+        let now = time();
+        let record = HealthRecord {
+            id: self.next_health_record_id,
+            animal_id,
+            diagnosis: payload.diagnosis.trim().to_string(),
+            symptoms: payload.symptoms.iter().map(|symptom| symptom.trim().to_string()).collect(),
+            temperature: payload.temperature,
+            treating_vet: payload.treating_vet,
+            lab_results: payload.lab_results,
+            follow_up_date: payload.follow_up_date,
+            notes: payload.notes,
+            recorded_at: now,
+            recorded_by: caller,
+            updated_at: None,
+        };
+        let animal = self.get_animal_mut(animal_id)?;
+        animal.health_records.push(record.clone());
+        animal.updated_at = Some(now);
+        self.next_health_record_id += 1;
+
+        self.log_event(caller, Some(animal_id), LivestockEvent::HealthRecordAdded { record: record.clone() });
+        Ok(record)
+    }
+
+    // Looks up a health record by ID across the herd
+    fn get_health_record(&self, record_id: u64) -> Result<&HealthRecord, LivestockError> {
+        self.animal
+            .values()
+            .flat_map(|animal| animal.health_records.iter())
+            .find(|record| record.id == record_id)
+            .ok_or_else(|| LivestockError::NotFound {
+                msg: format!("a health record with id={} not found", record_id),
+            })
+    }
+
+    // function to edit a health record
+    fn update_health_record(&mut self, caller: Principal, record_id: u64, payload: HealthRecordPayload) -> Result<HealthRecord, LivestockError> {
+        self.validate_health_record(&payload)?;
+
+        let now = time();
+        let animal_id = self.get_health_record(record_id)?.animal_id;
+        let animal = self.get_animal_mut(animal_id)?;
+        let record = animal
+            .health_records
+            .iter_mut()
+            .find(|record| record.id == record_id)
+            .ok_or_else(|| LivestockError::NotFound {
+                msg: format!("a health record with id={} not found", record_id),
+            })?;
+        let before = record.clone();
+        record.diagnosis = payload.diagnosis.trim().to_string();
+        record.symptoms = payload.symptoms.iter().map(|symptom| symptom.trim().to_string()).collect();
+        record.temperature = payload.temperature;
+        record.treating_vet = payload.treating_vet;
+        record.lab_results = payload.lab_results;
+        record.follow_up_date = payload.follow_up_date;
+        record.notes = payload.notes;
+        record.updated_at = Some(now);
+        let updated = record.clone();
+        animal.updated_at = Some(now);
+
+        self.log_event(caller, Some(animal_id), LivestockEvent::HealthRecordUpdated { before, after: updated.clone() });
+        Ok(updated)
+    }
+
+    // function to delete a health record
+    fn delete_health_record(&mut self, caller: Principal, record_id: u64) -> Result<HealthRecord, LivestockError> {
+        let animal_id = self.get_health_record(record_id)?.animal_id;
+        let animal = self.get_animal_mut(animal_id)?;
+        let index = animal.health_records.iter().position(|record| record.id == record_id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a health record with id={} not found", record_id),
+        })?;
+        let record = animal.health_records.remove(index);
+        animal.updated_at = Some(time());
+
+        self.log_event(caller, Some(animal_id), LivestockEvent::HealthRecordDeleted { record: record.clone() });
+        Ok(record)
+    }
+
+    // One page of the health records across the herd whose diagnosis contains the given text, optionally recorded
+    // within a date range, oldest first
+    fn find_health_records_by_diagnosis(
+        &self,
+        diagnosis: &str,
+        from: Option<u64>,
+        to: Option<u64>,
+        cursor: Option<HealthRecordCursor>,
+        limit: Option<u32>,
+    ) -> Result<HealthRecordPage, LivestockError> {
+        let limit = page_size(limit)?;
+        let diagnosis = diagnosis.trim().to_lowercase();
+        let position = |record: &HealthRecord| (record.recorded_at, record.id);
+        let mut records: Vec<&HealthRecord> = self
+            .animal
+            .values()
+            .flat_map(|animal| animal.health_records.iter())
+            .filter(|record| {
+                record.diagnosis.to_lowercase().contains(&diagnosis)
+                    && !matches!(from, Some(from) if record.recorded_at < from)
+                    && !matches!(to, Some(to) if record.recorded_at > to)
+                    && !matches!(cursor, Some(cursor) if position(record) <= (cursor.recorded_at, cursor.id))
+            })
+            .collect();
+        records.sort_by_key(|record| position(record));
+        let next_cursor = if records.len() > limit {
+            records.truncate(limit);
+            records.last().map(|record| HealthRecordCursor { recorded_at: record.recorded_at, id: record.id })
+        } else {
+            None
+        };
+        Ok(HealthRecordPage { records: records.into_iter().cloned().collect(), next_cursor })
+    }
+
+    // function to record a single dose of medication given to an animal right now
+    fn track_medication(&mut self, caller: Principal, animal_id: u64, medication_name: String, dosage: String) -> Result<Medication, LivestockError> {
+        let now = time();
+        let medication = self.schedule_medication(caller, animal_id, MedicationPayload {
+            name: medication_name,
+            dosage,
+            start_date: now,
+            end_date: now,
+            frequency_hours: 0,
+            route: AdministrationRoute::Other,
+            prescribed_by: caller,
+        })?;
+        self.record_medication_dose(caller, medication.id)
+    }
+
+    // function to schedule a medication course for an animal
+    fn schedule_medication(&mut self, caller: Principal, animal_id: u64, payload: MedicationPayload) -> Result<Medication, LivestockError> {
+        self.validate_medication(&payload)?;
+
+        let medication_id = self.next_medication_id;
+        let animal = self.get_animal_mut(animal_id)?;
+        let medication = Medication {
+            id: medication_id,
+            name: payload.name,
+            dosage: payload.dosage,
+            start_date: payload.start_date,
+            end_date: payload.end_date,
+            animal_id,
+            frequency_hours: payload.frequency_hours,
+            route: payload.route,
+            prescribed_by: Some(payload.prescribed_by),
+            doses: Vec::new(),
+            status: MedicationStatus::Active,
+        };
+        animal.medical_records.push(medication.clone());
+        animal.updated_at = Some(time());
+        self.next_medication_id += 1;
+        self.log_event(caller, Some(animal_id), LivestockEvent::MedicationScheduled { medication: medication.clone() });
+        Ok(medication)
+    }
+
+    // function to edit an active medication course
+    fn update_medication(&mut self, caller: Principal, medication_id: u64, payload: MedicationPayload) -> Result<Medication, LivestockError> {
+        self.validate_medication(&payload)?;
+
+        let medication = self.get_active_medication_mut(medication_id)?;
+        let before = medication.clone();
+        medication.name = payload.name;
+        medication.dosage = payload.dosage;
+        medication.start_date = payload.start_date;
+        medication.end_date = payload.end_date;
+        medication.frequency_hours = payload.frequency_hours;
+        medication.route = payload.route;
+        medication.prescribed_by = Some(payload.prescribed_by);
+        if medication.doses.len() as u64 >= medication.expected_doses() {
+            medication.status = MedicationStatus::Completed;
+        }
+        let updated = medication.clone();
+        self.get_animal_mut(updated.animal_id)?.updated_at = Some(time());
+        self.log_event(caller, Some(updated.animal_id), LivestockEvent::MedicationUpdated { before, after: updated.clone() });
+        Ok(updated)
+    }
+
+    // function to cancel an active medication course
+    fn cancel_medication(&mut self, caller: Principal, medication_id: u64) -> Result<Medication, LivestockError> {
+        let medication = self.get_active_medication_mut(medication_id)?;
+        medication.status = MedicationStatus::Cancelled;
+        let cancelled = medication.clone();
+        self.get_animal_mut(cancelled.animal_id)?.updated_at = Some(time());
+        self.log_event(caller, Some(cancelled.animal_id), LivestockEvent::MedicationCancelled { medication_id });
+        Ok(cancelled)
+    }
+
+    // function to mark a dose of a medication as administered, completing the course after its last dose
+    fn record_medication_dose(&mut self, caller: Principal, medication_id: u64) -> Result<Medication, LivestockError> {
+        let medication = self.get_active_medication_mut(medication_id)?;
+        let dose = DoseRecord {
+            administered_at: time(),
+            administered_by: caller,
+        };
+        medication.doses.push(dose.clone());
+        if medication.doses.len() as u64 >= medication.expected_doses() {
+            medication.status = MedicationStatus::Completed;
+        }
+        let updated = medication.clone();
+        self.get_animal_mut(updated.animal_id)?.updated_at = Some(time());
+        self.log_event(caller, Some(updated.animal_id), LivestockEvent::MedicationDoseRecorded { medication_id, dose, status: updated.status });
+        Ok(updated)
+    }
+
+    // function to set or, with None, remove the withdrawal period of a medication product
+    fn set_withdrawal_period(&mut self, caller: Principal, product: String, period: Option<WithdrawalPeriod>) -> Result<(), LivestockError> {
+        let key = product.trim().to_lowercase();
+        if key.is_empty() {
+            return Err(LivestockError::InvalidInput {
+                msg: "medication product cannot be empty".to_string(),
+            });
+        }
+        let before = match period {
+            Some(period) if period.meat_days > MAX_WITHDRAWAL_DAYS || period.milk_days > MAX_WITHDRAWAL_DAYS => {
+                return Err(LivestockError::InvalidInput {
+                    msg: format!("withdrawal periods cannot be longer than {} days", MAX_WITHDRAWAL_DAYS),
+                });
+            }
+            Some(period) => self.withdrawal_periods.insert(key, period),
+            None => {
+                let removed = self.withdrawal_periods.remove(&key);
+                if removed.is_none() {
+                    return Err(LivestockError::NotFound {
+                        msg: format!("no withdrawal period for medication product {}", product),
+                    });
+                }
+                removed
+            }
+        };
+
+        self.log_event(caller, None, LivestockEvent::WithdrawalPeriodUpdated { product, before, after: period });
+        Ok(())
+    }
+
+    // When the meat and milk of an animal are safe, the last dose of each medication plus the withdrawal period of its product
+    fn withdrawal(&self, animal: &Livestock) -> Withdrawal {
+        let mut withdrawal = Withdrawal {
+            animal_id: animal.id,
+            meat_safe_after: None,
+            milk_safe_after: None,
+        };
+        for medication in &animal.medical_records {
+            let Some(period) = self.withdrawal_periods.get(&medication.name.trim().to_lowercase()) else {
+                continue;
+            };
+            let Some(last_dose) = medication.doses.iter().map(|dose| dose.administered_at).max() else {
+                continue;
+            };
+            let meat_safe_after = last_dose.saturating_add(period.meat_days as u64 * NANOS_PER_DAY);
+            let milk_safe_after = last_dose.saturating_add(period.milk_days as u64 * NANOS_PER_DAY);
+            withdrawal.meat_safe_after = withdrawal.meat_safe_after.max(Some(meat_safe_after));
+            withdrawal.milk_safe_after = withdrawal.milk_safe_after.max(Some(milk_safe_after));
+        }
+        withdrawal
+    }
+
+    // Animals in the herd whose meat or milk is held back at the given time, by id
+    fn animals_under_withdrawal(&self, now: u64) -> Vec<Withdrawal> {
+        let mut withdrawals: Vec<Withdrawal> = self
+            .animal
+            .values()
+            .filter(|animal| animal.in_herd())
+            .map(|animal| self.withdrawal(animal))
+            .filter(|withdrawal| withdrawal.meat_withheld(now) || withdrawal.milk_withheld(now))
+            .collect();
+        withdrawals.sort_by_key(|withdrawal| withdrawal.animal_id);
+        withdrawals
+    }
+
+    // Checks a medication payload, the prescribing principal has to be a vet
+    fn validate_medication(&self, payload: &MedicationPayload) -> Result<(), LivestockError> {
+        if payload.name.trim().is_empty() {
+            return Err(LivestockError::InvalidInput {
+                msg: "medication name cannot be empty".to_string(),
+            });
+        }
+        if payload.frequency_hours > MAX_DOSE_FREQUENCY_HOURS {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("frequency of {} hours is longer than a year", payload.frequency_hours),
+            });
+        }
+        if payload.end_date < payload.start_date {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("end date {} is before start date {}", payload.end_date, payload.start_date),
+            });
+        }
+        if self.roles.get(&payload.prescribed_by) != Some(&Role::Vet) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("prescribing principal {} is not a vet", payload.prescribed_by),
+            });
+        }
+        Ok(())
+    }
+
+    // Looks up a medication by ID across the herd, it must still be active to be changed
+    fn get_active_medication_mut(&mut self, medication_id: u64) -> Result<&mut Medication, LivestockError> {
+        let medication = self
+            .animal
+            .values_mut()
+            .flat_map(|animal| animal.medical_records.iter_mut())
+            .find(|medication| medication.id == medication_id)
+            .ok_or_else(|| LivestockError::NotFound {
+                msg: format!("a medication with id={} not found", medication_id),
+            })?;
+        if medication.status != MedicationStatus::Active {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("medication with id={} is {:?}", medication_id, medication.status),
+            });
+        }
+        Ok(medication)
+    }
+
+    // Medications of the animals in the herd matching the filter
+    fn find_medications(&self, filter: impl Fn(&Medication) -> bool) -> Vec<Medication> {
+        let mut medications: Vec<Medication> = self
+            .animal
+            .values()
+            .filter(|animal| animal.in_herd())
+            .flat_map(|animal| animal.medical_records.iter())
+            .filter(|medication| filter(medication))
+            .cloned()
+            .collect();
+        medications.sort_by_key(|medication| medication.id);
+        medications
+    }
+
+    // function to add a vaccine protocol
+    fn add_vaccine_protocol(&mut self, caller: Principal, payload: VaccineProtocolPayload) -> Result<VaccineProtocol, LivestockError> {
+        if payload.vaccine_name.trim().is_empty() {
+            return Err(LivestockError::InvalidInput {
+                msg: "vaccine name cannot be empty".to_string(),
+            });
+        }
+        if payload.doses_required == 0 {
+            return Err(LivestockError::InvalidInput {
+                msg: "a vaccine protocol needs at least one dose".to_string(),
+            });
+        }
+        if payload.doses_required > 1 && payload.dose_interval_days == 0 {
+            return Err(LivestockError::InvalidInput {
+                msg: "dose interval must be at least one day".to_string(),
+            });
+        }
+        if payload.booster_interval_days == Some(0) {
+            return Err(LivestockError::InvalidInput {
+                msg: "booster interval must be at least one day".to_string(),
+            });
+        }
+        let longest = payload.first_dose_age_days.max(payload.dose_interval_days).max(payload.booster_interval_days.unwrap_or(0));
+        if longest > MAX_PROTOCOL_DAYS {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("ages and intervals cannot be longer than {} days", MAX_PROTOCOL_DAYS),
+            });
+        }
+
+        let protocol = VaccineProtocol {
+            id: self.next_protocol_id,
+            vaccine_name: payload.vaccine_name,
+            breed: payload.breed,
+            species: payload.species,
+            first_dose_age_days: payload.first_dose_age_days,
+            doses_required: payload.doses_required,
+            dose_interval_days: payload.dose_interval_days,
+            booster_interval_days: payload.booster_interval_days,
+        };
+        self.vaccine_protocols.insert(protocol.id, protocol.clone());
+        self.next_protocol_id += 1;
+        self.log_event(caller, None, LivestockEvent::VaccineProtocolAdded { protocol: protocol.clone() });
+        Ok(protocol)
+    }
+
+    // function to remove a vaccine protocol
+    fn remove_vaccine_protocol(&mut self, caller: Principal, protocol_id: u64) -> Result<VaccineProtocol, LivestockError> {
+        let protocol = self.vaccine_protocols.remove(&protocol_id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a vaccine protocol with id={} not found", protocol_id),
+        })?;
+        self.log_event(caller, None, LivestockEvent::VaccineProtocolRemoved { protocol: protocol.clone() });
+        Ok(protocol)
+    }
+
+    // function to record the next dose of a vaccine protocol given to an animal
+    fn record_vaccination(&mut self, caller: Principal, animal_id: u64, protocol_id: u64) -> Result<VaccinationRecord, LivestockError> {
+        let protocol = self.vaccine_protocols.get(&protocol_id).cloned().ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a vaccine protocol with id={} not found", protocol_id),
+        })?;
+        let now = time();
+        let animal = self.get_animal_mut(animal_id)?;
+        if !animal.in_herd() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} is no longer in the herd", animal_id),
+            });
+        }
+        if !protocol.applies_to(animal) {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("vaccine protocol with id={} does not apply to animal with id={}", protocol_id, animal_id),
+            });
+        }
+        // the course is done and the protocol has no boosters
+        let Some(next) = protocol.next_due(animal, now) else {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} already had the {} doses of vaccine protocol with id={}", animal_id, protocol.doses_required, protocol_id),
+            });
+        };
+
+        let record = VaccinationRecord {
+            protocol_id,
+            dose_number: next.dose_number,
+            administered_at: now,
+            administered_by: caller,
+        };
+        animal.vaccinations.push(record.clone());
+        animal.updated_at = Some(now);
+        self.log_event(caller, Some(animal_id), LivestockEvent::VaccinationRecorded { record: record.clone() });
+        Ok(record)
+    }
+
+    // The next dose of every protocol that applies to the animal
+    fn vaccination_schedule(&self, animal: &Livestock, now: u64) -> Vec<DueVaccination> {
+        let mut protocols: Vec<&VaccineProtocol> = self.vaccine_protocols.values().filter(|protocol| protocol.applies_to(animal)).collect();
+        protocols.sort_by_key(|protocol| protocol.id);
+        protocols.into_iter().filter_map(|protocol| protocol.next_due(animal, now)).collect()
+    }
+
+    // Vaccinations across the herd that are overdue or due within the given number of days
+    fn due_vaccinations(&self, within_days: u32, now: u64) -> Vec<DueVaccination> {
+        let horizon = now.saturating_add((within_days as u64).saturating_mul(NANOS_PER_DAY));
+        let mut due: Vec<DueVaccination> = self
+            .animal
+            .values()
+            .filter(|animal| animal.in_herd())
+            .flat_map(|animal| self.vaccination_schedule(animal, now))
+            .filter(|vaccination| vaccination.due_date <= horizon)
+            .collect();
+        due.sort_by_key(|vaccination| (vaccination.due_date, vaccination.animal_id));
+        due
+    }
+
+    // Raises a health alert the first time a vaccination becomes overdue
+    fn raise_overdue_vaccination_alerts(&mut self, now: u64) -> usize {
+        let overdue: Vec<DueVaccination> = self.due_vaccinations(0, now).into_iter().filter(|vaccination| vaccination.overdue).collect();
+        let mut raised = 0;
+        for vaccination in overdue {
+            let key = (vaccination.animal_id, vaccination.protocol_id, vaccination.dose_number);
+            if !self.vaccination_alerts.insert(key) {
+                continue;
+            }
+            let message = format!("{} dose {} overdue since {}", vaccination.vaccine_name, vaccination.dose_number, vaccination.due_date);
+            self.raise_alert(vaccination.animal_id, AlertKind::VaccinationOverdue, AlertSeverity::Low, message, now);
+            raised += 1;
+        }
+        raised
+    }
+
+    // Adds an open alert, returns its ID
+    fn raise_alert(&mut self, animal_id: u64, kind: AlertKind, severity: AlertSeverity, message: String, now: u64) -> u64 {
+        let id = self.next_alert_id;
+        let status = self.get_animal(animal_id).map(|animal| animal.healthstatus).unwrap_or_default();
+        self.health_alerts.push(HealthAlert {
+            id,
+            animal_id,
+            kind,
+            status,
+            timestamp: now,
+            message,
+            severity,
+            acknowledged_by: None,
+            acknowledged_at: None,
+            resolved_by: None,
+            resolved_at: None,
+            escalated_at: None,
+        });
+        self.next_alert_id += 1;
+        id
+    }
+
+    // Looks up an open alert by ID
+    fn get_open_alert_mut(&mut self, alert_id: u64) -> Result<&mut HealthAlert, LivestockError> {
+        let index = self.health_alerts.binary_search_by_key(&alert_id, |alert| alert.id).map_err(|_| LivestockError::NotFound {
+            msg: format!("an alert with id={} not found", alert_id),
+        })?;
+        let alert = &mut self.health_alerts[index];
+        if !alert.is_open() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("alert with id={} is already resolved", alert_id),
+            });
+        }
+        Ok(alert)
+    }
+
+    // function to acknowledge an open alert, which stops its escalation
+    fn acknowledge_alert(&mut self, caller: Principal, alert_id: u64) -> Result<HealthAlert, LivestockError> {
+        let alert = self.get_open_alert_mut(alert_id)?;
+        if alert.acknowledged_at.is_some() {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("alert with id={} is already acknowledged", alert_id),
+            });
+        }
+        alert.acknowledged_by = Some(caller);
+        alert.acknowledged_at = Some(time());
+        let acknowledged = alert.clone();
+        self.log_event(caller, Some(acknowledged.animal_id), LivestockEvent::AlertAcknowledged { alert_id });
+        Ok(acknowledged)
+    }
+
+    // function to resolve an open alert, acknowledged or not
+    fn resolve_alert(&mut self, caller: Principal, alert_id: u64) -> Result<HealthAlert, LivestockError> {
+        let alert = self.get_open_alert_mut(alert_id)?;
+        alert.resolved_by = Some(caller);
+        alert.resolved_at = Some(time());
+        let resolved = alert.clone();
+        self.log_event(caller, Some(resolved.animal_id), LivestockEvent::AlertResolved { alert_id });
+        Ok(resolved)
+    }
+
+    // function to get one page of all the health alerts, resolved ones included, oldest first
+    fn health_alerts_page(&self, cursor: Option<u64>, limit: Option<u32>) -> Result<AlertPage, LivestockError> {
+        let limit = page_size(limit)?;
+        let start = cursor.map_or(0, |cursor| self.health_alerts.partition_point(|alert| alert.id <= cursor));
+        let mut alerts: Vec<HealthAlert> = self.health_alerts.iter().skip(start).take(limit + 1).cloned().collect();
+        let next_cursor = if alerts.len() > limit {
+            alerts.truncate(limit);
+            alerts.last().map(|alert| alert.id)
+        } else {
+            None
+        };
+        Ok(AlertPage { alerts, next_cursor })
+    }
+
+    // Open alerts at or above a severity, most severe and then oldest first
+    fn open_alerts(&self, min_severity: Option<AlertSeverity>) -> Vec<HealthAlert> {
+        let mut alerts: Vec<HealthAlert> = self
+            .health_alerts
+            .iter()
+            .filter(|alert| alert.is_open() && !matches!(min_severity, Some(min) if alert.severity < min))
+            .cloned()
+            .collect();
+        alerts.sort_by_key(|alert| (std::cmp::Reverse(alert.severity), alert.id));
+        alerts
+    }
+
+    // function to change how long an alert for a sick animal can stay unacknowledged before it is escalated
+    fn set_alert_escalation_window(&mut self, caller: Principal, hours: u32) -> Result<u32, LivestockError> {
+        if hours == 0 || hours > MAX_ALERT_ESCALATION_HOURS {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("escalation window has to be between 1 and {} hours", MAX_ALERT_ESCALATION_HOURS),
+            });
+        }
+        let before = self.alert_escalation_hours;
+        self.alert_escalation_hours = hours;
+        self.log_event(caller, None, LivestockEvent::AlertEscalationWindowChanged { before, after: hours });
+        Ok(hours)
+    }
+
+    // Raises the severity of open alerts raised by an animal turning sick by one level for every escalation
+    // window they stay unacknowledged. Returns how many alerts were escalated.
+    fn escalate_alerts(&mut self, actor: Principal, now: u64) -> usize {
+        let window = self.alert_escalation_hours as u64 * NANOS_PER_HOUR;
+        let mut escalated = Vec::new();
+        for alert in self.health_alerts.iter_mut() {
+            if !alert.is_open() || alert.acknowledged_at.is_some() || alert.kind != AlertKind::StatusChange(HealthStatus::Sick) {
+                continue;
+            }
+            let since = alert.escalated_at.unwrap_or(alert.timestamp);
+            if now.saturating_sub(since) < window {
+                continue;
+            }
+            if let Some(severity) = alert.severity.escalated() {
+                escalated.push((alert.id, alert.animal_id, alert.severity, severity));
+                alert.severity = severity;
+                alert.escalated_at = Some(now);
+            }
+        }
+        for (alert_id, animal_id, before, after) in &escalated {
+            self.log_event(actor, Some(*animal_id), LivestockEvent::AlertEscalated { alert_id: *alert_id, before: *before, after: *after });
+        }
+        escalated.len()
+    }
+
+    // function to delete an animal entered by mistake. Animals leaving the herd are disposed instead,
+    // so an animal with any history, alerts, breeding events, offspring or feed deliveries, cannot be deleted.
+    fn delete_animal(&mut self, caller: Principal, id: u64) -> Result<Livestock, LivestockError> {
+        if let Some(reason) = self.history_of(id)? {
+            return Err(LivestockError::InvalidInput {
+                msg: format!("animal with id={} {}, dispose of it instead", id, reason),
+            });
+        }
+        let animal = self.animal.remove(&id).ok_or_else(|| LivestockError::NotFound {
+            msg: format!("couldn't delete an animal with id={}. animal not found", id),
+        })?;
+        for group in self.groups.values_mut() {
+            group.member_ids.retain(|member_id| *member_id != id);
+        }
+
+        self.log_event(caller, Some(id), LivestockEvent::AnimalDeleted);
+
+        Ok(animal)
+    }
+
+    // First history found that other records keep of an animal, None if it has none
+    fn history_of(&self, id: u64) -> Result<Option<String>, LivestockError> {
+        let animal = self.get_animal(id)?;
+        if let Some(event) = animal.breeding_events.first() {
+            return Ok(Some(format!("has breeding event with id={}", event.id)));
+        }
+        if let Some(child) = self.animal.values().find(|animal| matches!(&animal.parent_ids, Some(parents) if parents.parent1_id == id || parents.parent2_id == id)) {
+            return Ok(Some(format!("is a parent of animal with id={}", child.id)));
+        }
+        if let Some(dam) = self.animal.values().find(|dam| dam.breeding_events.iter().any(|event| event.sire_id == id)) {
+            return Ok(Some(format!("is the sire of a service of animal with id={}", dam.id)));
+        }
+        if let Some(alert) = self.health_alerts.iter().find(|alert| alert.animal_id == id) {
+            return Ok(Some(format!("has health alert with id={}", alert.id)));
+        }
+        if let Some(delivery) = self.feed_deliveries.iter().find(|delivery| delivery.animal_ids.contains(&id) || delivery.target == FeedTarget::Animal(id)) {
+            return Ok(Some(format!("was fed by feed delivery with id={}", delivery.id)));
+        }
+        Ok(None)
+    }
+
+    // function to get the number of animals in the herd
+    fn get_total_animals(&self) -> u64 {
+        self.animal.values().filter(|animal| animal.in_herd()).count() as u64
+    }
+
+    // function to get the average age in years of the animals in the herd
+    fn get_average_age(&self, now: u64) -> f32 {
+        let ages: Vec<u64> = self
+            .animal
+            .values()
+            .filter(|animal| animal.in_herd())
+            .map(|animal| animal.age_at(now).days as u64)
+            .collect();
+        if ages.is_empty() {
+            return 0.0;
+        }
+        let average_days = ages.iter().sum::<u64>() as f64 / ages.len() as f64;
+        (average_days / DAYS_PER_YEAR) as f32
+    }
+
+    // function to count the animals in the herd in each age bracket
+    fn get_age_histogram(&self, now: u64) -> Vec<AgeBracket> {
+        let mut min_days = 0;
+        let mut histogram: Vec<AgeBracket> = AGE_BRACKETS
+            .iter()
+            .map(|(label, max_days)| {
+                let bracket = AgeBracket {
+                    label: label.to_string(),
+                    min_days,
+                    max_days: *max_days,
+                    count: 0,
+                };
+                min_days = max_days.unwrap_or(u32::MAX);
+                bracket
+            })
+            .collect();
+        for animal in self.animal.values().filter(|animal| animal.in_herd()) {
+            let days = animal.age_at(now).days;
+            if let Some(bracket) = histogram.iter_mut().find(|bracket| !matches!(bracket.max_days, Some(max_days) if days >= max_days)) {
+                bracket.count += 1;
+            }
+        }
+        histogram
+    }
+
+    // function to get the health status statistics of the animals in the herd, or in a location and its sub-locations
+    fn get_health_status_statistics(&self, location_id: Option<u64>) -> HashMap<HealthStatus, u64> {
+        let mut statistics = HashMap::new();
+        for animal in self.animal.values().filter(|animal| animal.in_herd() && !matches!(location_id, Some(location_id) if !self.is_within(animal.location_id, location_id))) {
+            *statistics.entry(animal.healthstatus).or_insert(0) += 1;
+        }
+        statistics
+    }
+
+    // function to get the number of animals per breed in the herd, or in a location and its sub-locations
+    fn get_animals_per_breed(&self, location_id: Option<u64>) -> HashMap<String, u64> {
+        let mut breed_count = HashMap::new();
+        for animal in self.animal.values().filter(|animal| animal.in_herd() && !matches!(location_id, Some(location_id) if !self.is_within(animal.location_id, location_id))) {
+            *breed_count.entry(animal.breed.clone()).or_insert(0) += 1;
+        }
+        breed_count
+    }
+
+
+}
+
+// Looks up a breeding event of a dam by its ID
+fn find_breeding_event(dam: &mut Livestock, breeding_event_id: u64) -> Result<&mut BreedingEvent, LivestockError> {
+    let dam_id = dam.id;
+    dam.breeding_events
+        .iter_mut()
+        .find(|event| event.id == breeding_event_id)
+        .ok_or_else(|| LivestockError::NotFound {
+            msg: format!("a breeding event with id={} not found on animal with id={}", breeding_event_id, dam_id),
+        })
+}
+
+// Brings an identifier to the form it is stored in: trimmed and uppercase, RFID and microchip numbers
+// without spaces since readers and handlers group their digits differently
+fn normalize_identifier(kind: IdentifierKind, value: &str) -> Result<String, LivestockError> {
+    let value = match kind {
+        IdentifierKind::Rfid | IdentifierKind::Microchip => value.chars().filter(|c| !c.is_whitespace()).collect(),
+        IdentifierKind::VisualTag | IdentifierKind::NationalId => value.trim().to_uppercase(),
+    };
+    if value.is_empty() || value.chars().count() > MAX_IDENTIFIER_LENGTH {
+        return Err(LivestockError::InvalidInput {
+            msg: format!("identifiers have to be between 1 and {} characters long", MAX_IDENTIFIER_LENGTH),
+        });
+    }
+    if matches!(kind, IdentifierKind::Rfid | IdentifierKind::Microchip) && !value.chars().all(|c| c.is_ascii_digit()) {
+        return Err(LivestockError::InvalidInput {
+            msg: format!("{:?} numbers can only contain digits, got {}", kind, value),
+        });
+    }
+    Ok(value)
+}
+
+// Calving dates of an animal, oldest first: its births and the start of its current lactation
+fn calvings(animal: &Livestock) -> Vec<u64> {
+    let mut calvings: Vec<u64> = animal.breeding_events.iter().filter_map(|event| event.birth_date).collect();
+    if let ReproductiveStatus::Lactating { since } = animal.reproductive_status {
+        calvings.push(since);
+    }
+    calvings.sort_unstable();
+    calvings.dedup();
+    calvings
+}
+
+// Checks the name, nutrients and price of a feed
+fn validate_feed_item(payload: &FeedItemPayload) -> Result<(), LivestockError> {
+    let name = payload.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_FEED_NAME_LENGTH {
+        return Err(LivestockError::InvalidInput {
+            msg: format!("feed name has to be between 1 and {} characters long", MAX_FEED_NAME_LENGTH),
+        });
+    }
+    let NutrientProfile { dry_matter_percent, crude_protein_percent, crude_fiber_percent, energy_mj_per_kg } = payload.nutrients;
+    if ![dry_matter_percent, crude_protein_percent, crude_fiber_percent].iter().all(|percent| (0.0..=100.0).contains(percent)) {
+        return Err(LivestockError::InvalidInput {
+            msg: "nutrient percentages have to be between 0 and 100".to_string(),
+        });
+    }
+    if !(0.0..=MAX_FEED_ENERGY).contains(&energy_mj_per_kg) {
+        return Err(LivestockError::InvalidInput {
+            msg: format!("energy has to be between 0 and {} MJ per kg", MAX_FEED_ENERGY),
+        });
+    }
+    if !payload.cost_per_kg.is_finite() || payload.cost_per_kg < 0.0 {
+        return Err(LivestockError::InvalidInput {
+            msg: "cost per kg must be a non-negative number".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Checks the details supplied when creating or updating an animal
+fn validate_animal_details(payload: &LivestockPayload) -> Result<(), LivestockError> {
+    if payload.breed.trim().is_empty() {
+        return Err(LivestockError::InvalidInput {
+            msg: "breed cannot be empty".to_string(),
+        });
+    }
+    if !payload.height.is_finite() || payload.height < 0.0 {
+        return Err(LivestockError::InvalidInput {
+            msg: format!("height must be a non-negative number, got {}", payload.height),
+        });
+    }
+    if payload.date_of_birth > time() {
+        return Err(LivestockError::InvalidInput {
+            msg: "date of birth cannot be in the future".to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Checks the page size asked for, DEFAULT_PAGE_SIZE when none is given
+fn page_size(limit: Option<u32>) -> Result<usize, LivestockError> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if limit == 0 || limit > MAX_PAGE_SIZE {
+        return Err(LivestockError::InvalidInput {
+            msg: format!("limit has to be between 1 and {}", MAX_PAGE_SIZE),
+        });
+    }
+    Ok(limit as usize)
+}
+
+// One page of animals already in page order, starting after the cursor
+fn animal_page(matches: Vec<(AnimalCursor, &Livestock)>, descending: bool, cursor: Option<AnimalCursor>, limit: usize) -> AnimalPage {
+    let position = |key: &AnimalCursor| (key.sort_value, key.id);
+    let total = matches.len() as u64;
+    let start = match cursor {
+        Some(cursor) if descending => matches.partition_point(|(key, _)| position(key) >= position(&cursor)),
+        Some(cursor) => matches.partition_point(|(key, _)| position(key) <= position(&cursor)),
+        None => 0,
+    };
+    let page: Vec<(AnimalCursor, &Livestock)> = matches.into_iter().skip(start).take(limit + 1).collect();
+    let next_cursor = if page.len() > limit { Some(page[limit - 1].0) } else { None };
+
+    AnimalPage {
+        animals: page.into_iter().take(limit).map(|(_, animal)| animal.clone()).collect(),
+        next_cursor,
+        total,
+    }
+}
+
+// Thread local state of the canister. The memory manager hands out the stable
+// memory region used to carry the system across upgrades.
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> = RefCell::new(
+        MemoryManager::init(DefaultMemoryImpl::default())
+    );
+
+    static LIVESTOCK_SYSTEM: RefCell<LivestockManagementSystem> = RefCell::new(LivestockManagementSystem::new());
+}
+
+fn upgrades_memory() -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(UPGRADES_MEMORY_ID))
+}
+
+// Initialize the canister state
+#[ic_cdk_macros::init]
+fn init() {
+    ic_cdk::println!("Initializing Livestock Management System...");
+    let mut system = LivestockManagementSystem::new();
+
+    // The principal installing the canister becomes the first owner
+    system.roles.insert(ic_cdk::caller(), Role::Owner);
+    LIVESTOCK_SYSTEM.with(|s| *s.borrow_mut() = system);
+    start_timers();
+    ic_cdk::println!("Livestock Management System Initialized.");
+}
+
+// Serialize the whole system into stable memory before the wasm module is replaced
+#[ic_cdk_macros::pre_upgrade]
+fn pre_upgrade() {
+    let mut memory = upgrades_memory();
+    let len = LIVESTOCK_SYSTEM.with_borrow(|system| save_state(system, &mut memory));
+    ic_cdk::println!("Saved {} bytes of livestock system state.", len);
+}
+
+// Restore the system saved by pre_upgrade so animals, alerts and logs survive the upgrade
+#[ic_cdk_macros::post_upgrade]
+fn post_upgrade() {
+    // Canisters installed before pre_upgrade existed have nothing saved, they start over
+    let mut system = load_state(&upgrades_memory()).unwrap_or_else(|| {
+        ic_cdk::println!("No saved livestock system state, starting with an empty system.");
+        LivestockManagementSystem::new()
+    });
+
+    // A system that started over has no owner yet, hand it to the principal running the upgrade
+    if !system.roles.values().any(|role| *role == Role::Owner) {
+        system.roles.insert(ic_cdk::caller(), Role::Owner);
+    }
+    ic_cdk::println!("Restored livestock system with {} animals.", system.get_total_animals());
+    LIVESTOCK_SYSTEM.with(|s| *s.borrow_mut() = system);
+    start_timers();
+}
+
+// Writes the system as a length prefix followed by its CBOR encoding, returns the encoded length
+fn save_state<M: StableMemory>(system: &LivestockManagementSystem, memory: &mut M) -> u32 {
+    let mut state_bytes = vec![];
+    ciborium::ser::into_writer(system, &mut state_bytes).expect("Failed to encode the livestock system state.");
+    let len = u32::try_from(state_bytes.len())
+        .unwrap_or_else(|_| ic_cdk::trap(&format!("Livestock system state of {} bytes is too large to save.", state_bytes.len())));
+
+    let mut writer = Writer::new(memory, 0);
+    writer.write(&len.to_le_bytes()).expect("Failed to write the state length.");
+    writer.write(&state_bytes).expect("Failed to write the livestock system state.");
+    len
+}
+
+// Reads back the system written by save_state. None when nothing was saved, reading an empty memory would trap.
+fn load_state<M: StableMemory>(memory: &M) -> Option<LivestockManagementSystem> {
+    if memory.size() == 0 {
+        return None;
+    }
+    let mut len_bytes = [0; 4];
+    memory.read(0, &mut len_bytes);
+    let len = u32::from_le_bytes(len_bytes) as usize;
+    if len == 0 {
+        return None;
+    }
+
+    let mut state_bytes = vec![0; len];
+    memory.read(4, &mut state_bytes);
+    let system = ciborium::de::from_reader(&*state_bytes).expect("Failed to decode the livestock system state.");
+    Some(system)
+}
+
+// Timers do not survive upgrades, so they are registered again from init and post_upgrade
+fn start_timers() {
+    ic_cdk_timers::set_timer_interval(MAINTENANCE_INTERVAL, || {
+        let raised = LIVESTOCK_SYSTEM.with_borrow_mut(|system| system.raise_overdue_vaccination_alerts(time()));
+        if raised > 0 {
+            ic_cdk::println!("ALERT: {} vaccinations became overdue", raised);
+        }
+        // the canister itself is the actor of the escalations and compaction it runs
+        let escalated = LIVESTOCK_SYSTEM.with_borrow_mut(|system| system.escalate_alerts(ic_cdk::api::id(), time()));
+        if escalated > 0 {
+            ic_cdk::println!("ALERT: {} unacknowledged alerts escalated", escalated);
+        }
+        let removed = LIVESTOCK_SYSTEM.with_borrow_mut(|system| system.compact_events(ic_cdk::api::id(), time()));
+        if removed > 0 {
+            ic_cdk::println!("Dropped {} event log entries past their retention", removed);
+        }
+    });
+}
+
+
+#[ic_cdk_macros::update]
+fn create_animal(payload: LivestockPayload) -> Result<u64, LivestockError> {
+    ic_cdk::println!("Creating animal born at: {}, breed: {}, height: {}, sex: {:?}, species: {:?}", payload.date_of_birth, payload.breed, payload.height, payload.sex, payload.species);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        let id = system.create_animal(caller, payload)?;
+        ic_cdk::println!("Animal created with ID: {}", id);
+        Ok(id)
+    })
+}
+
+// Record the service of a dam by a sire, naturally or by artificial insemination
+#[ic_cdk_macros::update]
+fn record_service(dam_id: u64, sire_id: u64, service_date: u64, method: ServiceMethod) -> Result<BreedingEvent, LivestockError> {
+    ic_cdk::println!("Recording service of animal with ID: {} by animal with ID: {}", dam_id, sire_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.record_service(caller, dam_id, sire_id, service_date, method)
+    })
+}
+
+// Record the result of a pregnancy check
+#[ic_cdk_macros::update]
+fn record_pregnancy_check(dam_id: u64, breeding_event_id: u64, pregnant: bool) -> Result<BreedingEvent, LivestockError> {
+    ic_cdk::println!("Recording pregnancy check of animal with ID: {}: {}", dam_id, pregnant);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.record_pregnancy_check(caller, dam_id, breeding_event_id, pregnant)
+    })
+}
+
+// Record a birth and create its offspring, returns the IDs of the new animals
+#[ic_cdk_macros::update]
+fn record_birth(dam_id: u64, breeding_event_id: u64, birth_date: u64, offspring: Vec<OffspringPayload>) -> Result<Vec<u64>, LivestockError> {
+    ic_cdk::println!("Recording birth of {} offspring from animal with ID: {}", offspring.len(), dam_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.record_birth(caller, dam_id, breeding_event_id, birth_date, offspring)
+    })
+}
+
+// Query function to get the births expected within N days, overdue ones included
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_expected_births(within_days: u32) -> Vec<ExpectedBirth> {
+    let now = time();
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.expected_births(within_days, now)
+    })
+}
+
+// Record the parents of an existing animal
+#[ic_cdk_macros::update]
+fn set_parents(id: u64, parent1_id: u64, parent2_id: u64) -> Result<Livestock, LivestockError> {
+    ic_cdk::println!("Setting parents of animal with ID: {} to {} and {}", id, parent1_id, parent2_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.set_parents(caller, id, parent1_id, parent2_id)
+    })
+}
+
+// Get Pedigree function to get the ancestor tree of an animal by ID
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_pedigree(id: u64, max_generations: u32) -> Result<PedigreeNode, LivestockError> {
+    ic_cdk::println!("Getting pedigree of animal with ID: {} over {} generations", id, max_generations);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_pedigree(id, max_generations)
+    })
+}
+
+// Get the inbreeding coefficient the offspring of two animals would have
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_inbreeding_coefficient(parent1_id: u64, parent2_id: u64) -> Result<f64, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_animal(parent1_id)?;
+        system.get_animal(parent2_id)?;
+        Ok(system.inbreeding_coefficient(parent1_id, parent2_id))
+    })
+}
+
+// Rank the possible mates of an animal in the herd by how closely related they are to it
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn suggest_mates(animal_id: u64, limit: Option<u32>) -> Result<Vec<MateSuggestion>, LivestockError> {
+    ic_cdk::println!("Suggesting mates for animal with ID: {}", animal_id);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.suggest_mates(animal_id, limit)
+    })
+}
+
+// Change the inbreeding threshold and whether record_service refuses or only warns above it
+#[ic_cdk_macros::update]
+fn set_inbreeding_policy(policy: InbreedingPolicy) -> Result<InbreedingPolicy, LivestockError> {
+    ic_cdk::println!("Setting inbreeding policy to {:?}", policy);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.set_inbreeding_policy(caller, policy)
+    })
+}
+
+// Query function to get the inbreeding policy
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_inbreeding_policy() -> InbreedingPolicy {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.inbreeding_policy
+    })
+}
+
+// Get Descendants function to get the children, grandchildren and further offspring of an animal by ID
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_descendants(id: u64, max_generations: u32) -> Result<Vec<Descendant>, LivestockError> {
+    ic_cdk::println!("Getting descendants of animal with ID: {} over {} generations", id, max_generations);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_descendants(id, max_generations)
+    })
+}
+
+// Read function to get the animal details by ID
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_animal(id: u64) -> Result<Livestock, LivestockError> {
+    ic_cdk::println!("Getting animal with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        let animal = system.get_animal(id)?;
+        ic_cdk::println!("Animal found: {:?}", animal);
+        Ok(animal.clone())
+    })
+}
+
+// Get the current age of an animal in days, months and years query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_animal_age(id: u64) -> Result<Age, LivestockError> {
+    ic_cdk::println!("Getting age of animal with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        Ok(system.get_animal(id)?.age_at(time()))
+    })
+}
+
+// Query function to list the animals matching a filter one page at a time
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn list_animals(filter: AnimalFilter, sort: AnimalSort, cursor: Option<AnimalCursor>, limit: Option<u32>) -> Result<AnimalPage, LivestockError> {
+    ic_cdk::println!("Listing animals matching {:?} sorted by {:?}", filter, sort);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.list_animals(&filter, sort, cursor, limit, time())
+    })
+}
+
+// Function to replace the tags of an animal
+#[ic_cdk_macros::update]
+fn set_tags(id: u64, tags: Vec<String>) -> Result<Livestock, LivestockError> {
+    ic_cdk::println!("Setting tags of animal with ID: {} to {:?}", id, tags);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.set_tags(caller, id, tags)
+    })
+}
+
+// Function to give an animal an RFID, visual tag, national registration number or microchip identifier
+#[ic_cdk_macros::update]
+fn add_identifier(id: u64, kind: IdentifierKind, value: String) -> Result<Identifier, LivestockError> {
+    ic_cdk::println!("Adding {:?} identifier {} to animal with ID: {}", kind, value, id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.add_identifier(caller, id, kind, value)
+    })
+}
+
+// Function to replace an identifier of an animal, e.g. after a lost ear tag
+#[ic_cdk_macros::update]
+fn retag_animal(id: u64, kind: IdentifierKind, value: String, reason: String) -> Result<Identifier, LivestockError> {
+    ic_cdk::println!("Retagging {:?} identifier of animal with ID: {} to {}", kind, id, value);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.retag_animal(caller, id, kind, value, reason)
+    })
+}
+
+// Query function to find the animal carrying a scanned or read identifier
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn find_animal_by_identifier(kind: IdentifierKind, value: String) -> Result<Livestock, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.find_animal_by_identifier(kind, &value).cloned()
+    })
+}
+
+// Function to add a barn, pen or paddock
+#[ic_cdk_macros::update]
+fn add_location(payload: LocationPayload) -> Result<Location, LivestockError> {
+    ic_cdk::println!("Adding {:?} {}", payload.kind, payload.name);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.add_location(caller, payload)
+    })
+}
+
+// Function to edit a location by its ID
+#[ic_cdk_macros::update]
+fn update_location(id: u64, payload: LocationPayload) -> Result<Location, LivestockError> {
+    ic_cdk::println!("Updating location with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.update_location(caller, id, payload)
+    })
+}
+
+// Function to remove an empty location by its ID
+#[ic_cdk_macros::update]
+fn remove_location(id: u64) -> Result<Location, LivestockError> {
+    ic_cdk::println!("Removing location with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.remove_location(caller, id)
+    })
+}
+
+// Query function to get every location, by id
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_locations() -> Vec<Location> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        let mut locations: Vec<Location> = system.locations.values().cloned().collect();
+        locations.sort_by_key(|location| location.id);
+        locations
+    })
+}
+
+// Function to move animals to a location, or out of any location with None
+#[ic_cdk_macros::update]
+fn move_animals(ids: Vec<u64>, location_id: Option<u64>) -> Result<Vec<Movement>, LivestockError> {
+    ic_cdk::println!("Moving animals {:?} to location {:?}", ids, location_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.move_animals(caller, ids, location_id)
+    })
+}
+
+// Query function to get one page of the animals kept in a location, optionally with its sub-locations
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_animals_in_location(location_id: u64, include_sub_locations: bool, cursor: Option<AnimalCursor>, limit: Option<u32>) -> Result<AnimalPage, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.animals_in_location(location_id, include_sub_locations, cursor, limit)
+    })
+}
+
+// Query function to get the occupancy of every location against its capacity
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_location_occupancy() -> Vec<LocationOccupancy> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.location_occupancy())
+}
+
+// Query function to get the moves of an animal between locations, oldest first
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_movement_history(animal_id: u64) -> Result<Vec<Movement>, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        Ok(system.get_animal(animal_id)?.movements.clone())
+    })
+}
+
+// Function to create a named group of animals
+#[ic_cdk_macros::update]
+fn create_group(name: String, kind: GroupKind, member_ids: Vec<u64>) -> Result<HerdGroup, LivestockError> {
+    ic_cdk::println!("Creating {:?} group {}", kind, name);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.create_group(caller, name, kind, member_ids)
+    })
+}
+
+// Function to rename a group or change its kind
+#[ic_cdk_macros::update]
+fn update_group(id: u64, name: String, kind: GroupKind) -> Result<HerdGroup, LivestockError> {
+    ic_cdk::println!("Updating group with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.update_group(caller, id, name, kind)
+    })
+}
+
+// Function to add animals to a group and remove others from it
+#[ic_cdk_macros::update]
+fn change_group_members(id: u64, add: Vec<u64>, remove: Vec<u64>) -> Result<HerdGroup, LivestockError> {
+    ic_cdk::println!("Changing members of group with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.change_group_members(caller, id, add, remove)
+    })
+}
+
+// Function to delete a group by its ID
+#[ic_cdk_macros::update]
+fn delete_group(id: u64) -> Result<HerdGroup, LivestockError> {
+    ic_cdk::println!("Deleting group with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.delete_group(caller, id)
+    })
+}
+
+// Query function to get a group by its ID
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_group(id: u64) -> Result<HerdGroup, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.get_group(id).cloned())
+}
+
+// Query function to get every group, by id
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_groups() -> Vec<HerdGroup> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        let mut groups: Vec<HerdGroup> = system.groups.values().cloned().collect();
+        groups.sort_by_key(|group| group.id);
+        groups
+    })
+}
+
+// Function to record a dose of the same medication given to a group or a list of animals
+#[ic_cdk_macros::update]
+fn batch_track_medication(target: BatchTarget, medication_name: String, dosage: String) -> Result<Vec<BatchOutcome>, LivestockError> {
+    ic_cdk::println!("Tracking medication {} for {:?}", medication_name, target);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.batch_track_medication(caller, target, medication_name, dosage)
+    })
+}
+
+// Function to change the health status of a group or a list of animals
+#[ic_cdk_macros::update]
+fn batch_update_health_status(target: BatchTarget, new_status: HealthStatus, note: String) -> Result<Vec<BatchOutcome>, LivestockError> {
+    ic_cdk::println!("Updating health status of {:?} to {:?}", target, new_status);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.batch_update_health_status(caller, target, new_status, note)
+    })
+}
+
+// Function to move a group or a list of animals to a location, or out of any location with None
+#[ic_cdk_macros::update]
+fn batch_move_animals(target: BatchTarget, location_id: Option<u64>) -> Result<Vec<BatchOutcome>, LivestockError> {
+    ic_cdk::println!("Moving {:?} to location {:?}", target, location_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.batch_move_animals(caller, target, location_id)
+    })
+}
+
+// Function to add a feed to the inventory
+#[ic_cdk_macros::update]
+fn add_feed_item(payload: FeedItemPayload) -> Result<FeedItem, LivestockError> {
+    ic_cdk::println!("Adding feed {}", payload.name);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.add_feed_item(caller, payload)
+    })
+}
+
+// Function to edit a feed by its ID
+#[ic_cdk_macros::update]
+fn update_feed_item(id: u64, payload: FeedItemPayload) -> Result<FeedItem, LivestockError> {
+    ic_cdk::println!("Updating feed with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.update_feed_item(caller, id, payload)
+    })
+}
+
+// Function to add a purchase of a feed to its stock
+#[ic_cdk_macros::update]
+fn restock_feed(id: u64, quantity_kg: f64) -> Result<FeedItem, LivestockError> {
+    ic_cdk::println!("Restocking feed with ID: {} with {} kg", id, quantity_kg);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.restock_feed(caller, id, quantity_kg)
+    })
+}
+
+// Query function to get the feed inventory, by id
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_feed_items() -> Vec<FeedItem> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        let mut items: Vec<FeedItem> = system.feed_items.values().cloned().collect();
+        items.sort_by_key(|item| item.id);
+        items
+    })
+}
+
+// Function to put a group on a new ration
+#[ic_cdk_macros::update]
+fn set_ration_plan(group_id: u64, components: Vec<RationComponent>) -> Result<RationPlan, LivestockError> {
+    ic_cdk::println!("Setting ration plan of group with ID: {}", group_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.set_ration_plan(caller, group_id, components)
+    })
+}
+
+// Function to take a group off its ration
+#[ic_cdk_macros::update]
+fn end_ration_plan(group_id: u64) -> Result<RationPlan, LivestockError> {
+    ic_cdk::println!("Ending ration plan of group with ID: {}", group_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.end_ration_plan(caller, group_id)
+    })
+}
+
+// Query function to get the ration plans of a group, or of every group, oldest first
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_ration_plans(group_id: Option<u64>) -> Vec<RationPlan> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.ration_plans.iter().filter(|plan| !matches!(group_id, Some(group_id) if plan.group_id != group_id)).cloned().collect()
+    })
+}
+
+// Function to record feed handed out to a group or an animal
+#[ic_cdk_macros::update]
+fn record_feed_delivery(payload: FeedDeliveryPayload) -> Result<FeedDelivery, LivestockError> {
+    ic_cdk::println!("Recording delivery of {} kg of feed {} to {:?}", payload.quantity_kg, payload.feed_id, payload.target);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.record_feed_delivery(caller, payload)
+    })
+}
+
+// Query function to get the feed deliveries to a group, or shared by an animal, within a date range
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_feed_deliveries(target: Option<FeedTarget>, from: Option<u64>, to: Option<u64>) -> Vec<FeedDelivery> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.find_feed_deliveries(target, from, to).into_iter().cloned().collect()
+    })
+}
+
+// Query function to get the feed cost per head and feed conversion ratio of a group or an animal
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_feed_report(target: FeedTarget, from: Option<u64>, to: Option<u64>) -> Result<FeedReport, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.feed_report(target, from, to)
+    })
+}
+
+// Query function to get the feed cost per head and feed conversion ratio of a ration plan
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_ration_report(ration_id: u64) -> Result<FeedReport, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.ration_report(ration_id)
+    })
+}
+
+// Update function to update the animal details by ID
+#[ic_cdk_macros::update]
+fn update_animal(id: u64, payload: LivestockPayload) -> Result<Livestock, LivestockError> {
+    ic_cdk::println!("Updating animal with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        let animal = system.update_animal(caller, id, payload)?;
+        ic_cdk::println!("Animal updated: {:?}", animal);
+        Ok(animal)
+    })
+}
+
+
+// Function to record an animal in heat, pregnant with its expected due date, lactating or open
+#[ic_cdk_macros::update]
+fn update_reproductive_status(id: u64, status: ReproductiveStatus) -> Result<Livestock, LivestockError> {
+    ic_cdk::println!("Updating reproductive status of animal with ID: {} to {:?}", id, status);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.update_reproductive_status(caller, id, status)
+    })
+}
+
+// Function to record an animal being sold, dying, culled or transferred out of the herd
+#[ic_cdk_macros::update]
+fn dispose_animal(id: u64, payload: DispositionPayload) -> Result<Livestock, LivestockError> {
+    ic_cdk::println!("Recording {:?} of animal with ID: {}", payload.kind, id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        // A vet may record a death, every other disposition is the owner's decision
+        let allowed: &[Role] = if payload.kind == DispositionKind::Died { &[Role::Owner, Role::Vet] } else { &[Role::Owner] };
+        system.authorize(caller, allowed)?;
+        system.dispose_animal(caller, id, payload)
+    })
+}
+
+// Query function to get one page of the animals that left the herd, optionally of one kind and within a date range
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_disposed_animals(kind: Option<DispositionKind>, from: Option<u64>, to: Option<u64>, cursor: Option<AnimalCursor>, limit: Option<u32>) -> Result<AnimalPage, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.disposed_animals(kind, from, to, cursor, limit)
+    })
+}
+
+// Function to record a sale of milk from an animal, refused while its milk is under withdrawal
+#[ic_cdk_macros::update]
+fn record_milk_sale(id: u64, litres: f64, buyer: String) -> Result<(), LivestockError> {
+    ic_cdk::println!("Recording sale of {} litres of milk from animal with ID: {}", litres, id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.record_milk_sale(caller, id, litres, buyer)
+    })
+}
+
+// Function to record a milking of a dairy animal
+#[ic_cdk_macros::update]
+fn record_milking(id: u64, payload: MilkRecordPayload) -> Result<MilkRecord, LivestockError> {
+    ic_cdk::println!("Recording milking of {} litres for animal with ID: {}", payload.volume_litres, id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.record_milking(caller, id, payload)
+    })
+}
+
+// Get the milkings of an animal query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_milk_records(id: u64, from: Option<u64>, to: Option<u64>) -> Result<Vec<MilkRecord>, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_milk_records(id, from, to)
+    })
+}
+
+// Get the lactation curve, days in milk and 305-day yield of an animal query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_lactation_curve(id: u64, calving_date: Option<u64>) -> Result<LactationCurve, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.lactation_curve(id, calving_date, time())
+    })
+}
+
+// Get the daily milk totals of the herd query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_herd_milk_totals(from: Option<u64>, to: Option<u64>) -> Vec<DailyMilkTotal> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.herd_milk_totals(from, to)
+    })
+}
+
+// Function to change the somatic cell count from which a milking raises a mastitis alert
+#[ic_cdk_macros::update]
+fn set_mastitis_threshold(cell_count: u32) -> Result<u32, LivestockError> {
+    ic_cdk::println!("Setting mastitis threshold to {} cells/ml", cell_count);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.set_mastitis_threshold(caller, cell_count)
+    })
+}
+
+// Query function to get the somatic cell count from which a milking raises a mastitis alert
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_mastitis_threshold() -> u32 {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.mastitis_cell_count)
+}
+
+// Function to record a weight, height or body condition measurement
+#[ic_cdk_macros::update]
+fn record_measurement(id: u64, payload: MeasurementPayload) -> Result<Measurement, LivestockError> {
+    ic_cdk::println!("Recording measurement for animal with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.record_measurement(caller, id, payload)
+    })
+}
+
+// Get the measurement history of an animal query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_measurements(id: u64, from: Option<u64>, to: Option<u64>) -> Result<Vec<Measurement>, LivestockError> {
+    ic_cdk::println!("Getting measurements of animal with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_measurements(id, from, to)
+    })
+}
+
+// Get the average daily gain of an animal over a date range query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_average_daily_gain(id: u64, from: Option<u64>, to: Option<u64>) -> Result<f64, LivestockError> {
+    ic_cdk::println!("Getting average daily gain of animal with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.average_daily_gain(id, from, to)
+    })
+}
+
+// Function to set or remove the growth benchmark of a breed
+#[ic_cdk_macros::update]
+fn set_growth_benchmark(breed: String, min_daily_gain: Option<f64>) -> Result<(), LivestockError> {
+    ic_cdk::println!("Setting growth benchmark for breed {} to {:?}", breed, min_daily_gain);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.set_growth_benchmark(caller, breed, min_daily_gain)
+    })
+}
+
+// Get the growth benchmarks per breed query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_growth_benchmarks() -> HashMap<String, f64> {
+    ic_cdk::println!("Getting growth benchmarks...");
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.growth_benchmarks.clone()
+    })
+}
+
+// Get animals growing below their breed benchmark query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_growth_below_benchmark(from: Option<u64>, to: Option<u64>) -> Vec<GrowthReport> {
+    ic_cdk::println!("Getting animals growing below their breed benchmark...");
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.growth_below_benchmark(from, to)
+    })
+}
+
+// Function to update the animal health status
+#[ic_cdk_macros::update]
+fn update_health_status(id: u64, new_status: HealthStatus, note: String) -> Result<Livestock, LivestockError> {
+
+    ic_cdk::println!("Updating health status of animal with ID: {} to {:?}", id, new_status);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.update_health_status(caller, id, new_status, note)
+    })
+}
+
+// Query function to get the health status history of an animal, oldest first
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_status_history(id: u64) -> Result<Vec<StatusChange>, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        Ok(system.get_animal(id)?.status_history.clone())
+    })
+}
+
+// Query function to get the time in nanoseconds an animal spent in each health status
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_time_in_status(id: u64) -> Result<HashMap<HealthStatus, u64>, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.time_in_status(id, time())
+    })
+}
+
+// Function to replace the health status transitions update_health_status allows
+#[ic_cdk_macros::update]
+fn set_health_transitions(transitions: Vec<HealthTransition>) -> Result<Vec<HealthTransition>, LivestockError> {
+    ic_cdk::println!("Setting health status transitions to {:?}", transitions);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.set_health_transitions(caller, transitions)
+    })
+}
+
+// Query function to get the health status transitions update_health_status allows
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_health_transitions() -> Vec<HealthTransition> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.health_transitions.clone())
+}
+
+// Function to add a health record to an animal
+#[ic_cdk_macros::update]
+fn add_health_record(animal_id: u64, payload: HealthRecordPayload) -> Result<HealthRecord, LivestockError> {
+    ic_cdk::println!("Adding health record {} for animal with ID: {}", payload.diagnosis, animal_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.add_health_record(caller, animal_id, payload)
+    })
+}
+
+// Query function to get a health record by ID
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_health_record(record_id: u64) -> Result<HealthRecord, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_health_record(record_id).cloned()
+    })
+}
+
+// Query function to get the health records of an animal, oldest first
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_health_records(animal_id: u64) -> Result<Vec<HealthRecord>, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        Ok(system.get_animal(animal_id)?.health_records.clone())
+    })
+}
+
+// Function to edit a health record
+#[ic_cdk_macros::update]
+fn update_health_record(record_id: u64, payload: HealthRecordPayload) -> Result<HealthRecord, LivestockError> {
+    ic_cdk::println!("Updating health record with ID: {}", record_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.update_health_record(caller, record_id, payload)
+    })
+}
+
+// Function to delete a health record
+#[ic_cdk_macros::update]
+fn delete_health_record(record_id: u64) -> Result<HealthRecord, LivestockError> {
+    ic_cdk::println!("Deleting health record with ID: {}", record_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.delete_health_record(caller, record_id)
+    })
+}
+
+// Query function to get one page of the health records across the herd matching a diagnosis, for outbreak tracking
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn find_health_records_by_diagnosis(
+    diagnosis: String,
+    from: Option<u64>,
+    to: Option<u64>,
+    cursor: Option<HealthRecordCursor>,
+    limit: Option<u32>,
+) -> Result<HealthRecordPage, LivestockError> {
+    ic_cdk::println!("Finding health records with diagnosis: {}", diagnosis);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.find_health_records_by_diagnosis(&diagnosis, from, to, cursor, limit)
+    })
+}
+
+
+// Display implementation for HealthStatus
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthStatus::Healthy => write!(f, "Healthy"),
+            HealthStatus::Sick => write!(f, "Sick"),
+            HealthStatus::Critical => write!(f, "Critical"),
+            HealthStatus::Recovering => write!(f, "Recovering"),
+        }
+    }
+}
+
+
+// Function to get one page of the health alerts
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_health_alerts(cursor: Option<u64>, limit: Option<u32>) -> Result<AlertPage, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.health_alerts_page(cursor, limit)
+    })
+}
+
+// Query function to get the open alerts at or above a severity, most severe first
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_open_alerts(min_severity: Option<AlertSeverity>) -> Vec<HealthAlert> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.open_alerts(min_severity)
+    })
+}
+
+// Function to acknowledge an open alert
+#[ic_cdk_macros::update]
+fn acknowledge_alert(alert_id: u64) -> Result<HealthAlert, LivestockError> {
+    ic_cdk::println!("Acknowledging alert with ID: {}", alert_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, ANIMAL_MANAGERS)?;
+        system.acknowledge_alert(caller, alert_id)
+    })
+}
+
+// Function to resolve an open alert
+#[ic_cdk_macros::update]
+fn resolve_alert(alert_id: u64) -> Result<HealthAlert, LivestockError> {
+    ic_cdk::println!("Resolving alert with ID: {}", alert_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.resolve_alert(caller, alert_id)
+    })
+}
+
+// Function to change how long an alert for a sick animal can stay unacknowledged before it is escalated
+#[ic_cdk_macros::update]
+fn set_alert_escalation_window(hours: u32) -> Result<u32, LivestockError> {
+    ic_cdk::println!("Setting alert escalation window to {} hours", hours);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.set_alert_escalation_window(caller, hours)
+    })
+}
+
+// Query function to get the alert escalation window in hours
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_alert_escalation_window() -> u32 {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.alert_escalation_hours)
+}
+
+// Medication tracking function
+#[ic_cdk_macros::update]
+fn track_medication(animal_id: u64, medication_name: String, dosage: String) -> Result<Medication, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        let medication = system.track_medication(caller, animal_id, medication_name, dosage)?;
+        ic_cdk::println!("Medication tracked for animal with ID: {}", animal_id);
+        Ok(medication)
+    })
+}
+
+// Schedule a medication course with its own dates, frequency, route and prescribing vet
+#[ic_cdk_macros::update]
+fn schedule_medication(animal_id: u64, payload: MedicationPayload) -> Result<Medication, LivestockError> {
+    ic_cdk::println!("Scheduling medication {} for animal with ID: {}", payload.name, animal_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.schedule_medication(caller, animal_id, payload)
+    })
+}
+
+// Edit an active medication course by its ID
+#[ic_cdk_macros::update]
+fn update_medication(medication_id: u64, payload: MedicationPayload) -> Result<Medication, LivestockError> {
+    ic_cdk::println!("Updating medication with ID: {}", medication_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.update_medication(caller, medication_id, payload)
+    })
+}
+
+// Cancel an active medication course by its ID
+#[ic_cdk_macros::update]
+fn cancel_medication(medication_id: u64) -> Result<Medication, LivestockError> {
+    ic_cdk::println!("Cancelling medication with ID: {}", medication_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.cancel_medication(caller, medication_id)
+    })
+}
+
+// Mark the next dose of a medication as administered by the caller
+#[ic_cdk_macros::update]
+fn record_medication_dose(medication_id: u64) -> Result<Medication, LivestockError> {
+    ic_cdk::println!("Recording dose of medication with ID: {}", medication_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.record_medication_dose(caller, medication_id)
+    })
+}
+
+// Query function to get the medications in progress across the herd
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_active_medications() -> Vec<Medication> {
+    let now = time();
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.find_medications(|medication| medication.is_active(now))
+    })
+}
+
+// Query function to get the medications with a missed dose across the herd
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_overdue_medications() -> Vec<Medication> {
+    let now = time();
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.find_medications(|medication| medication.is_overdue(now))
+    })
+}
+
+// Set the withdrawal period of a medication product, or remove it with None
+#[ic_cdk_macros::update]
+fn set_withdrawal_period(product: String, period: Option<WithdrawalPeriod>) -> Result<(), LivestockError> {
+    ic_cdk::println!("Setting withdrawal period of {} to {:?}", product, period);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.set_withdrawal_period(caller, product, period)
+    })
+}
+
+// Query function to get the withdrawal periods per medication product
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_withdrawal_periods() -> HashMap<String, WithdrawalPeriod> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.withdrawal_periods.clone())
+}
+
+// Query function to get when the meat and milk of an animal are safe to sell or slaughter
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_withdrawal(animal_id: u64) -> Result<Withdrawal, LivestockError> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        Ok(system.withdrawal(system.get_animal(animal_id)?))
+    })
+}
+
+// Query function to get the animals whose meat or milk is currently held back
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_animals_under_withdrawal() -> Vec<Withdrawal> {
+    let now = time();
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.animals_under_withdrawal(now)
+    })
+}
+
+// Add a vaccine protocol for a breed, or for the whole herd when no breed is given
+#[ic_cdk_macros::update]
+fn add_vaccine_protocol(payload: VaccineProtocolPayload) -> Result<VaccineProtocol, LivestockError> {
+    ic_cdk::println!("Adding vaccine protocol for {}", payload.vaccine_name);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.add_vaccine_protocol(caller, payload)
+    })
+}
+
+// Remove a vaccine protocol by its ID
+#[ic_cdk_macros::update]
+fn remove_vaccine_protocol(protocol_id: u64) -> Result<VaccineProtocol, LivestockError> {
+    ic_cdk::println!("Removing vaccine protocol with ID: {}", protocol_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner, Role::Vet])?;
+        system.remove_vaccine_protocol(caller, protocol_id)
+    })
+}
+
+// Query function to get all the vaccine protocols
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_vaccine_protocols() -> Vec<VaccineProtocol> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        let mut protocols: Vec<VaccineProtocol> = system.vaccine_protocols.values().cloned().collect();
+        protocols.sort_by_key(|protocol| protocol.id);
+        protocols
+    })
+}
+
+// Record the next dose of a vaccine protocol given to an animal
+#[ic_cdk_macros::update]
+fn record_vaccination(animal_id: u64, protocol_id: u64) -> Result<VaccinationRecord, LivestockError> {
+    ic_cdk::println!("Recording vaccination of protocol {} for animal with ID: {}", protocol_id, animal_id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Vet])?;
+        system.record_vaccination(caller, animal_id, protocol_id)
+    })
+}
+
+// Query function to get the next vaccinations an animal is due for
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_vaccination_schedule(animal_id: u64) -> Result<Vec<DueVaccination>, LivestockError> {
+    let now = time();
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        let animal = system.get_animal(animal_id)?;
+        Ok(system.vaccination_schedule(animal, now))
+    })
+}
+
+// Query function to get the vaccinations across the herd that are overdue or due within N days
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_due_vaccinations(within_days: u32) -> Vec<DueVaccination> {
+    let now = time();
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.due_vaccinations(within_days, now)
+    })
+}
+
+// Query function to get one page of the event log entries matching a query, newest first
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_event_logs(query: EventQuery, cursor: Option<u64>, limit: Option<u32>) -> Result<EventPage, LivestockError> {
+    ic_cdk::println!("Getting event logs matching {:?}", query);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_event_logs(&query, cursor, limit)
+    })
+}
+
+// Query function to get the audit trail of an animal, oldest entries first
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_audit_trail(animal_id: u64, cursor: Option<u64>, limit: Option<u32>) -> Result<EventPage, LivestockError> {
+    ic_cdk::println!("Getting audit trail of animal with ID: {}", animal_id);
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_audit_trail(animal_id, cursor, limit)
+    })
+}
+
+// Function to change how much of the event log is kept
+#[ic_cdk_macros::update]
+fn set_event_retention(retention: EventRetention) -> Result<EventRetention, LivestockError> {
+    ic_cdk::println!("Setting event retention to {:?}", retention);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.set_event_retention(caller, retention)
+    })
+}
+
+// Query function to get the event retention policy
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_event_retention() -> EventRetention {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.event_retention)
+}
+
+
+// Get total number of animals query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_total_animals() -> u64 {
+    ic_cdk::println!("Getting total number of animals...");
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_total_animals()
+    })
 }
 
-// Vacination and medication tracking
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
-struct Medication {
-    id: u64,
-    name: String,
-    dosage: String,
-    start_date: u64,
-    end_date: u64,
+// Get average age of all the animals query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_average_age() -> f32 {
+    ic_cdk::println!("Getting average age of all the animals...");
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_average_age(time())
+    })
 }
 
-// Parent IDs struct of the animal
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
-struct ParentIds {
-    parent1_id: u64,
-    parent2_id: u64,
+// Get the number of living animals per age bracket query
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_age_histogram() -> Vec<AgeBracket> {
+    ic_cdk::println!("Getting the age histogram of the herd...");
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_age_histogram(time())
+    })
 }
 
-// Health alert struct
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
-struct HealthAlert {
-    animal_id: u64,
-    status: HealthStatus,
-    timestamp: u64,
+// Get number of animals per breed query, optionally in a location
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_animals_per_breed(location_id: Option<u64>) -> HashMap<String, u64> {
+    ic_cdk::println!("Getting number of animals per breed...");
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_animals_per_breed(location_id)
+    })
 }
 
-// Event logging struct
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Debug)]
-struct EventLog {
-    event_type: String,
-    details: String,
-    timestamp: u64,
+// Get health status statistics of all the animals query, optionally in a location
+#[ic_cdk_macros::query(guard = "caller_can_read")]
+fn get_health_status_statistics(location_id: Option<u64>) -> HashMap<HealthStatus, u64> {
+    ic_cdk::println!("Getting health status statistics of all the animals...");
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.get_health_status_statistics(location_id)
+    })
 }
 
-// Health status struct of the animal
-#[derive(candid::CandidType, Clone, Serialize, Deserialize, Copy, Default)]
-#[derive(Debug)]
-#[derive(PartialEq)]
-#[derive(Hash)]
-#[derive(Eq)]
-enum HealthStatus {
-    #[default]  // Default status is Healthy
-    Healthy,
-    Sick,
-    Critical,
-    Recovering,
+// Query guard rejecting principals without a role, so viewers and up can read the herd records
+fn caller_can_read() -> Result<(), String> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.authorize(ic_cdk::caller(), READERS)).map_err(|error| format!("{:?}", error))
 }
 
+// Query guard rejecting every principal but owners
+fn caller_is_owner() -> Result<(), String> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| system.authorize(ic_cdk::caller(), &[Role::Owner])).map_err(|error| format!("{:?}", error))
+}
 
-// Using HashMap to store animal records where each animal has a unique ID
-struct LivestockManagementSystem {
-    animal: HashMap<u32, Livestock>,   // Strores animals by their id
-    next_id: u64,   // This is a counter to generate unique IDs
-    health_alerts: Vec<HealthAlert>,  // Stores health alerts
-    event_logs: Vec<EventLog>,  // Stores event logs
+// Grant a role to a principal. Only owners can manage roles.
+#[ic_cdk_macros::update]
+fn grant_role(principal: Principal, role: Role) -> Result<(), LivestockError> {
+    ic_cdk::println!("Granting role {:?} to principal: {}", role, principal);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.grant_role(caller, principal, role)
+    })
 }
 
+// Revoke the role of a principal. Only owners can manage roles.
+#[ic_cdk_macros::update]
+fn revoke_role(principal: Principal) -> Result<Role, LivestockError> {
+    ic_cdk::println!("Revoking role of principal: {}", principal);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        system.revoke_role(caller, principal)
+    })
+}
 
-impl LivestockManagementSystem {
+// Query function to get every principal and its role. Only owners can see the role table.
+#[ic_cdk_macros::query(guard = "caller_is_owner")]
+fn get_roles() -> Vec<(Principal, Role)> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.roles.iter().map(|(principal, role)| (*principal, *role)).collect()
+    })
+}
 
-    // function to initiliaze new management system
-    fn new() -> Self { LivestockManagementSystem {
-        animal: HashMap::new(),
-        next_id: 1,
-        health_alerts: Vec::new(),
-        event_logs: Vec::new(),
-    }}
+// Query function to get the role of the caller, open to any principal
+#[ic_cdk_macros::query]
+fn get_my_role() -> Option<Role> {
+    LIVESTOCK_SYSTEM.with_borrow(|system| {
+        system.roles.get(&ic_cdk::caller()).copied()
+    })
+}
 
-    // create_animal function
-    fn create_animal(&mut self, age: u8, breed: String, height: f32) -> u64 {
+// Delete function to delete the animal by ID
+#[ic_cdk_macros::update]
+fn delete_animal(id: u64) -> Result<Livestock, LivestockError> {
+    ic_cdk::println!("Deleting animal with ID: {}", id);
+    LIVESTOCK_SYSTEM.with_borrow_mut(|system| {
+        let caller = ic_cdk::caller();
+        system.authorize(caller, &[Role::Owner])?;
+        let animal = system.delete_animal(caller, id)?;
+        ic_cdk::println!("Animal deleted with ID: {}", id);
+        Ok(animal)
+    })
+}
 
-        let current_time = time();
-        let breed_clone = breed.clone();
 
-        // create new animal with unique ID
-        let animal = Livestock {
-            id: self.next_id,
-            age,
-            breed,
-            height,
-            healthrecords: "Healthy".to_string(),
-            healthstatus: HealthStatus::Healthy,
-            medical_records: Vec::new(),
-            parent_ids: None,
-            created_at: current_time,
-            updated_at: None,
-        };
 
-        // Insert animal into the HashMap
-        self.animal.insert(self.next_id.try_into().unwrap(), animal);
+// Export the candid functions
 
-        // log the event
-        self.event_logs.push(EventLog {
-            event_type: "Animal Created".to_string(),
-            details: format!("Animal with ID: {}, Breed: {}, Age: {}, Height: {} created.", self.next_id, breed_clone, age, height),
-            timestamp: current_time,
-        });
+ic_cdk::export_candid!(); 
 
-        // Increment ID to the next animal
-        self.next_id += 1;
 
-        // Return the ID of the new animal for reference
-        self.next_id - 1
-    }
 
-    // Breed animal function
-    fn breed_animals(&mut self, parent1_id: u64, parent2_id: u64, breed: String) -> Option<u64> {
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
 
-        // check if both parents exist
-        if self.animal.contains_key(&(parent1_id as u32)) && self.animal.contains_key(&(parent2_id as u32)) {
-            let breed_clone = breed.clone();
+    thread_local! {
+        static NOW: Cell<u64> = const { Cell::new(1_000 * NANOS_PER_DAY) };
+    }
 
-            // Create a new offspring
-            let offspring_id = self.create_animal(0, breed, 0.0);
+    // Stands in for ic_cdk::api::time(), which only works inside a canister
+    pub(super) fn time() -> u64 {
+        NOW.with(|now| now.get())
+    }
 
-            // Set the parents IDs of the offspring
-            if let Some(offspring) = self.animal.get_mut(&(offspring_id as u32)) {
-                offspring.parent_ids = Some(ParentIds {
-                    parent1_id,
-                    parent2_id,
-                });
-            }
+    fn set_time(now: u64) {
+        NOW.with(|cell| cell.set(now));
+    }
 
-            // log the event
-            self.event_logs.push(EventLog {
-                event_type: "Animal Bred".to_string(),
-                details: format!("Animal with ID: {} and ID: {} bred to create a new animal with ID: {} and breed: {}", parent1_id, parent2_id, offspring_id, breed_clone),
-                timestamp: time(),
-            });
+    // IDs that used to collide once cast to u32
+    const LOW_ID: u64 = 1;
+    const HIGH_ID: u64 = (1 << 32) + 1;
 
-            Some(offspring_id)
-        } else {
-            None
+    fn animal(id: u64, breed: &str) -> Livestock {
+        Livestock {
+            id,
+            breed: breed.to_string(),
+            ..Default::default()
         }
     }
 
-    // Get Peddigree function
-    fn get_pedigree(&self, id: u64) -> Vec<Livestock> {
-        let mut pedigree = Vec::new();
-        if let Some(animal) = self.animal.get(&(id as u32)) {
-            pedigree.push(animal.clone());
-            if let Some(parents) = &animal.parent_ids {
-                pedigree.append(&mut self.get_pedigree(parents.parent1_id));
-                pedigree.append(&mut self.get_pedigree(parents.parent2_id));
-            }
+    fn herd(ids: &[u64]) -> LivestockManagementSystem {
+        let mut system = LivestockManagementSystem::new();
+        for &id in ids {
+            system.animal.insert(id, animal(id, &format!("breed {}", id)));
         }
-        pedigree
+        system
     }
 
-
-    // function to get the total number of animals
-    fn get_total_animals(&self) -> u64 {
-        self.animal.len() as u64
+    fn owner() -> Principal {
+        Principal::from_slice(&[1])
     }
 
-    // function to get the average age of all the animals
-    fn get_average_age(&self) -> f32 {
-        let total_animals = self.get_total_animals();
-        let total_age: u64 = self.animal.values().map(|animal| animal.age as u64).sum();
-        if total_animals > 0 {
-            total_age as f32 / total_animals as f32
-        } else {
-            0.0
+    // A sire and dam with a son and a daughter
+    fn family() -> LivestockManagementSystem {
+        let mut system = herd(&[1, 2, 3, 4]);
+        for (id, sex) in [(1, Sex::Male), (2, Sex::Female), (3, Sex::Male), (4, Sex::Female)] {
+            system.animal.get_mut(&id).unwrap().sex = sex;
         }
+        for id in [3, 4] {
+            system.animal.get_mut(&id).unwrap().parent_ids = Some(ParentIds { parent1_id: 1, parent2_id: 2 });
+        }
+        system
     }
 
-    // function to get the average height of all the animals
-    fn get_average_height(&self) -> f32 {
-        let total_animals = self.get_total_animals();
-        ic_cdk::println!("Total animals: {}", total_animals);
+    #[test]
+    fn nothing_saved_starts_over() {
+        let memory = DefaultMemoryImpl::default();
+        assert!(load_state(&memory).is_none());
 
-        if total_animals == 0 {
-            return 0.0;
-        }
+        // a grown memory that was never written holds a zero length
+        memory.grow(1);
+        assert!(load_state(&memory).is_none());
+    }
 
-        let mut total_height = 0.0;
-        let mut valid_heights = 0;
+    #[test]
+    fn saved_state_survives_an_upgrade() {
+        let mut system = herd(&[1, HIGH_ID]);
+        system.next_id = HIGH_ID + 1;
+        system.roles.insert(Principal::anonymous(), Role::Vet);
+        system.raise_alert(1, AlertKind::StatusChange(HealthStatus::Sick), AlertSeverity::Medium, "Health status changed to Sick".to_string(), 5);
+        let mut memory = DefaultMemoryImpl::default();
+        save_state(&system, &mut memory);
 
-        for animal in self.animal.values() {
-            if !animal.height.is_nan() && animal.height > 0.0 {
-                total_height += animal.height;
-                valid_heights += 1;
-                ic_cdk::println!("Added height: {} for animal ID: {}", animal.height, animal.id);
-            }
-        }
+        let restored = load_state(&memory).unwrap();
+        assert_eq!(restored.state_version, STATE_VERSION);
+        assert_eq!(restored.animal[&HIGH_ID].breed, system.animal[&HIGH_ID].breed);
+        assert_eq!(restored.next_id, HIGH_ID + 1);
+        assert_eq!(restored.roles.get(&Principal::anonymous()), Some(&Role::Vet));
+        assert_eq!(restored.health_alerts.len(), 1);
+    }
 
-        ic_cdk::println!("Total height: {}, Valid measurement: {}", total_height, valid_heights);
+    #[test]
+    fn pairings_above_the_inbreeding_limit_are_refused() {
+        let mut system = family();
+        system.animal.insert(5, Livestock { sex: Sex::Female, ..animal(5, "Angus") });
+        system.animal.insert(6, Livestock { sex: Sex::Female, species: Species::Sheep, ..animal(6, "Merino") });
+        let service_date = time() - NANOS_PER_DAY;
 
-        if valid_heights > 0 {
-            let average = total_height / valid_heights as f32;
-            ic_cdk::println!("Average height: {}", average);
-            average
-        } else {
-            ic_cdk::println!("No valid height found.");
-            0.0
-        }
+        assert_eq!(system.inbreeding_coefficient(1, 2), 0.0);
+        assert_eq!(system.inbreeding_coefficient(3, 4), 0.25);
+        assert_eq!(system.inbreeding_coefficient(1, 4), 0.25);
+
+        let refused = system.record_service(owner(), 4, 3, service_date, ServiceMethod::Natural);
+        assert!(matches!(refused, Err(LivestockError::InbreedingLimit { .. })));
+        assert!(system.get_animal(4).unwrap().breeding_events.is_empty());
+
+        // unrelated dams come first, males and other species are not candidates
+        let mates: Vec<(u64, f64)> = system.suggest_mates(3, None).unwrap().iter().map(|mate| (mate.animal_id, mate.inbreeding_coefficient)).collect();
+        assert_eq!(mates, vec![(5, 0.0), (2, 0.25), (4, 0.25)]);
+        let mates: Vec<u64> = system.suggest_mates(3, Some(1)).unwrap().iter().map(|mate| mate.animal_id).collect();
+        assert_eq!(mates, vec![5]);
+
+        // a warning policy lets the pairing through and logs it
+        system.set_inbreeding_policy(owner(), InbreedingPolicy { threshold: 0.0625, action: InbreedingAction::Warn }).unwrap();
+        system.record_service(owner(), 4, 3, service_date, ServiceMethod::Natural).unwrap();
+        assert!(system.events.iter().any(|entry| matches!(entry.event, LivestockEvent::InbreedingWarning { sire_id: 3, .. })));
     }
 
-    // function to get the health status statistics of all the animals
-    fn get_health_status_statistics(&self) -> HashMap<HealthStatus, u64> {
-        let mut statistics = HashMap::new();
-        for animal in self.animal.values() {
-            *statistics.entry(animal.healthstatus).or_insert(0) += 1;
-        }
-        statistics
+    #[test]
+    fn births_follow_a_confirmed_service() {
+        let mut system = family();
+        system.next_id = 5;
+        let service_date = time() - 290 * NANOS_PER_DAY;
+
+        let service = system.record_service(owner(), 2, 1, service_date, ServiceMethod::ArtificialInsemination).unwrap();
+        assert_eq!(service.expected_due_date, service_date + 283 * NANOS_PER_DAY);
+        let due: Vec<u64> = system.expected_births(0, time()).iter().map(|birth| birth.breeding_event_id).collect();
+        assert_eq!(due, vec![service.id]);
+
+        system.record_pregnancy_check(owner(), 2, service.id, true).unwrap();
+        assert_eq!(system.get_animal(2).unwrap().reproductive_status, ReproductiveStatus::Pregnant { expected_due_date: service.expected_due_date });
+        // a pregnant dam cannot be served again
+        assert!(system.record_service(owner(), 2, 3, service_date, ServiceMethod::Natural).is_err());
+
+        let twins = vec![
+            OffspringPayload { sex: Sex::Female, birth_weight: 32.0, breed: None },
+            OffspringPayload { sex: Sex::Male, birth_weight: 35.5, breed: Some("Angus cross".to_string()) },
+        ];
+        assert!(system.record_birth(owner(), 2, service.id, service_date - 1, twins.clone()).is_err());
+        let birth_date = time() - NANOS_PER_DAY;
+        // room for one calf only, so neither is recorded
+        system.next_id = u64::MAX - 1;
+        assert!(system.record_birth(owner(), 2, service.id, birth_date, twins.clone()).is_err());
+        assert_eq!((system.animal.len(), system.next_id), (4, u64::MAX - 1));
+        assert_eq!(system.get_animal(2).unwrap().breeding_events[0].status, BreedingStatus::Pregnant);
+        system.next_id = 5;
+        let offspring = system.record_birth(owner(), 2, service.id, birth_date, twins.clone()).unwrap();
+        assert_eq!(offspring, vec![5, 6]);
+
+        let heifer = system.get_animal(5).unwrap();
+        assert_eq!((heifer.sex, heifer.breed.as_str(), heifer.date_of_birth), (Sex::Female, "breed 2", birth_date));
+        assert!(matches!(heifer.parent_ids, Some(ParentIds { parent1_id: 1, parent2_id: 2 })));
+        assert_eq!(heifer.measurements[0].weight, Some(32.0));
+        assert_eq!(system.get_animal(6).unwrap().breed, "Angus cross");
+
+        let dam = system.get_animal(2).unwrap();
+        assert_eq!(dam.reproductive_status, ReproductiveStatus::Lactating { since: birth_date });
+        assert_eq!(dam.breeding_events[0].status, BreedingStatus::Delivered);
+        assert!(system.expected_births(0, time()).is_empty());
+        assert!(system.record_birth(owner(), 2, service.id, birth_date, twins).is_err());
     }
 
-    // function to get the number of animals per breed
-    fn get_animals_per_breed(&self) -> HashMap<String, u64> {
-        let mut breed_count = HashMap::new();
-        for animal in self.animal.values() {
-            *breed_count.entry(animal.breed.clone()).or_insert(0) += 1;
+    #[test]
+    fn animals_are_filtered_by_location_and_details() {
+        let mut system = herd(&[1, 2, 3, 4]);
+        system.locations.insert(10, location(10, LocationKind::Barn, None, None));
+        system.locations.insert(11, location(11, LocationKind::Pen, Some(10), None));
+        for (id, location_id, sex, days_old) in [(1, Some(11), Sex::Female, 30), (2, Some(10), Sex::Male, 400), (3, None, Sex::Female, 900), (4, Some(11), Sex::Female, 60)] {
+            let animal = system.animal.get_mut(&id).unwrap();
+            animal.location_id = location_id;
+            animal.sex = sex;
+            animal.date_of_birth = time() - days_old * NANOS_PER_DAY;
         }
-        breed_count
+        system.animal.get_mut(&1).unwrap().tags = vec!["heifer".to_string(), "organic".to_string()];
+        system.animal.get_mut(&4).unwrap().disposition = Some(Disposition {
+            kind: DispositionKind::Sold,
+            date: time(),
+            reason: String::new(),
+            buyer: Some("Neighbour".to_string()),
+            recorded_by: owner(),
+        });
+        let ids = |filter: AnimalFilter, sort: AnimalSort| -> Vec<u64> {
+            system.list_animals(&filter, sort, None, None, time()).unwrap().animals.iter().map(|animal| animal.id).collect()
+        };
+        let by_id = AnimalSort::default();
+
+        // the barn holds its pens, a disposed animal is left out unless asked for
+        assert_eq!(ids(AnimalFilter { location_id: Some(10), ..Default::default() }, by_id), vec![1, 2]);
+        assert_eq!(ids(AnimalFilter { location_id: Some(10), include_disposed: Some(true), ..Default::default() }, by_id), vec![1, 2, 4]);
+        assert_eq!(ids(AnimalFilter { location_id: Some(11), ..Default::default() }, by_id), vec![1]);
+        assert_eq!(ids(AnimalFilter { breed: Some(" BREED 3 ".to_string()), ..Default::default() }, by_id), vec![3]);
+        assert_eq!(ids(AnimalFilter { sex: Some(Sex::Female), min_age_days: Some(31), ..Default::default() }, by_id), vec![3]);
+        assert_eq!(ids(AnimalFilter { tags: vec!["Organic".to_string()], ..Default::default() }, by_id), vec![1]);
+        assert_eq!(ids(AnimalFilter::default(), AnimalSort { key: AnimalSortKey::Age, descending: true }), vec![3, 2, 1]);
     }
 
+    #[test]
+    fn herd_wide_listings_are_paged() {
+        let mut system = herd(&[1, 2, 3]);
+        system.locations.insert(10, location(10, LocationKind::Barn, None, None));
+        for id in [1, 2, 3] {
+            let animal = system.animal.get_mut(&id).unwrap();
+            animal.location_id = Some(10);
+            animal.health_records.push(HealthRecord {
+                id,
+                animal_id: id,
+                diagnosis: "Pneumonia".to_string(),
+                symptoms: Vec::new(),
+                temperature: None,
+                treating_vet: owner(),
+                lab_results: Vec::new(),
+                follow_up_date: None,
+                notes: String::new(),
+                recorded_at: 10 - id,
+                recorded_by: owner(),
+                updated_at: None,
+            });
+            animal.disposition = (id != 2).then(|| Disposition {
+                kind: DispositionKind::Died,
+                date: 10 - id,
+                reason: String::new(),
+                buyer: None,
+                recorded_by: owner(),
+            });
+            system.raise_alert(id, AlertKind::StatusChange(HealthStatus::Sick), AlertSeverity::Medium, "Health status changed to Sick".to_string(), 0);
+        }
+        assert!(matches!(system.health_alerts_page(None, Some(0)), Err(LivestockError::InvalidInput { .. })));
 
-}
+        let first = system.health_alerts_page(None, Some(2)).unwrap();
+        let rest = system.health_alerts_page(first.next_cursor, Some(2)).unwrap();
+        let alerts: Vec<u64> = first.alerts.iter().chain(&rest.alerts).map(|alert| alert.animal_id).collect();
+        assert_eq!(alerts, vec![1, 2, 3]);
+        assert_eq!(rest.next_cursor, None);
 
-// Creating a mutable static instance of LivestockManagementSystem
-static mut LIVECTOCK_SYSTEM: Option<LivestockManagementSystem> = None;
+        // oldest records first
+        let first = system.find_health_records_by_diagnosis("pneumonia", None, None, None, Some(2)).unwrap();
+        let rest = system.find_health_records_by_diagnosis("pneumonia", None, None, first.next_cursor, Some(2)).unwrap();
+        let records: Vec<u64> = first.records.iter().chain(&rest.records).map(|record| record.animal_id).collect();
+        assert_eq!(records, vec![3, 2, 1]);
+        assert_eq!(rest.next_cursor, None);
 
-// Initialize the canister state
-#[ic_cdk_macros::init]
-fn init() {
-    ic_cdk::println!("Initializing Livestock Management System...");
-    unsafe {
-        LIVECTOCK_SYSTEM = Some(LivestockManagementSystem::new());
-        ic_cdk::println!("Livestock Management System Initialized.");
+        let first = system.disposed_animals(None, None, None, None, Some(1)).unwrap();
+        let rest = system.disposed_animals(None, None, None, first.next_cursor, Some(1)).unwrap();
+        assert_eq!((first.animals[0].id, rest.animals[0].id, rest.total), (3, 1, 2));
+        assert_eq!(rest.next_cursor, None);
+
+        // only animal 2 is still in the barn
+        let page = system.animals_in_location(10, false, None, Some(1)).unwrap();
+        assert_eq!((page.animals[0].id, page.total, page.next_cursor), (2, 1, None));
     }
-}
 
+    #[test]
+    fn health_status_follows_the_allowed_transitions() {
+        let mut system = herd(&[1]);
+        let vet = Principal::from_slice(&[2]);
+        let day = |n: u64| n * NANOS_PER_DAY;
+        system.animal.get_mut(&1).unwrap().status_history = vec![StatusChange { status: HealthStatus::Healthy, changed_at: 0, changed_by: owner(), note: String::new() }];
 
-#[ic_cdk_macros::update]
-fn create_animal(age: u8, breed: String, height: f32) -> u64 {
-    ic_cdk::println!("Creating animal with age: {}, breed: {}, height: {}", age, breed, height);
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_mut().expect("System not Initialized.");
-        let id = system.create_animal(age, breed, height);
-        ic_cdk::println!("Animal created with ID: {}", id); 
-        id
-    }
-}
+        set_time(day(1_000));
+        system.update_health_status(vet, 1, HealthStatus::Critical, "Bloat".to_string()).unwrap();
+        assert_eq!(system.open_alerts(None)[0].severity, AlertSeverity::Critical);
+        // a critical animal has to recover first
+        assert!(matches!(system.update_health_status(vet, 1, HealthStatus::Healthy, String::new()), Err(LivestockError::InvalidInput { .. })));
 
-// Breed function to create a new animal by breeding two existing animals
-#[ic_cdk_macros::update]
-fn breed_animals(parent1_id: u64, parent2_id: u64, breed: String) -> Option<u64> {
-    ic_cdk::println!("Breeding animals with parent IDs: {} and {} to create a new animal with breed: {}", parent1_id, parent2_id, breed);
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_mut().expect("System not Initialized.");
-        system.breed_animals(parent1_id, parent2_id, breed)
+        set_time(day(1_002));
+        system.update_health_status(vet, 1, HealthStatus::Recovering, "Eating again".to_string()).unwrap();
+        set_time(day(1_005));
+        system.update_health_status(vet, 1, HealthStatus::Healthy, String::new()).unwrap();
+        assert!(system.open_alerts(None).is_empty());
+
+        let history = &system.get_animal(1).unwrap().status_history;
+        let notes: Vec<&str> = history.iter().map(|change| change.note.as_str()).collect();
+        assert_eq!(notes, vec!["", "Bloat", "Eating again", ""]);
+        assert_eq!(history[1].changed_by, vet);
+
+        let durations = system.time_in_status(1, day(1_010)).unwrap();
+        assert_eq!(durations[&HealthStatus::Healthy], day(1_005));
+        assert_eq!(durations[&HealthStatus::Critical], day(2));
+        assert_eq!(durations[&HealthStatus::Recovering], day(3));
+        assert_eq!(durations[&HealthStatus::Sick], 0);
     }
-}
 
-// Get Pedigree function to get the pedigree of an animal by ID
-#[ic_cdk_macros::query]
-fn get_pedigree(id: u64) -> Vec<Livestock> {
-    ic_cdk::println!("Getting pedigree of animal with ID: {}", id);
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.get_pedigree(id)
+    #[test]
+    fn only_status_alerts_escalate_and_resolve_with_the_status() {
+        let mut system = herd(&[1]);
+        let vet = Principal::from_slice(&[2]);
+        let day = |n: u64| n * NANOS_PER_DAY;
+
+        set_time(day(1_000));
+        system.update_health_status(vet, 1, HealthStatus::Sick, String::new()).unwrap();
+        let milking = MilkRecord { id: 1, milked_at: day(1_000), volume_litres: 1.0, fat_percent: None, protein_percent: None, somatic_cell_count: Some(250_000), withheld: false, recorded_by: owner() };
+        let mastitis = system.raise_mastitis_alert(1, &milking, day(1_000)).unwrap();
+        let vaccination = system.raise_alert(1, AlertKind::VaccinationOverdue, AlertSeverity::Low, "Clostridial dose 1 overdue".to_string(), day(1_000));
+        let alert = |system: &LivestockManagementSystem, id: u64| system.health_alerts.iter().find(|alert| alert.id == id).cloned().unwrap();
+        // every alert records the animal was sick, only the kind tells them apart
+        assert!(system.health_alerts.iter().all(|alert| alert.status == HealthStatus::Sick));
+
+        assert_eq!(system.escalate_alerts(owner(), day(1_002)), 1);
+        assert_eq!(system.open_alerts(None)[0].kind, AlertKind::StatusChange(HealthStatus::Sick));
+        assert_eq!(alert(&system, mastitis).severity, AlertSeverity::Medium);
+        assert_eq!(alert(&system, vaccination).severity, AlertSeverity::Low);
+
+        system.update_health_status(vet, 1, HealthStatus::Healthy, String::new()).unwrap();
+        let open: Vec<u64> = system.open_alerts(None).iter().map(|alert| alert.id).collect();
+        assert_eq!(open, vec![mastitis, vaccination]);
     }
-}
 
-// Read function to get the animal details by ID
-#[ic_cdk_macros::query]
-fn get_animal(id: u64) -> Option<Livestock> {
-    ic_cdk::println!("Getting animal with ID: {}", id);
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        match system.animal.get(&(id as u32)) {
-            Some(animal) => {
-                ic_cdk::println!("Animal found: {:?}", animal);
-                Some(animal.clone())
-            }
-            None => {
-                ic_cdk::println!("No animal found with ID: {}", id);
-                None
-            }
-        }
+    #[test]
+    fn treated_animals_are_held_back_until_withdrawal_ends() {
+        let mut system = herd(&[1, 2]);
+        let vet = Principal::from_slice(&[2]);
+        system.roles.insert(vet, Role::Vet);
+        system.animal.get_mut(&1).unwrap().sex = Sex::Female;
+        let day = |n: u64| n * NANOS_PER_DAY;
+        let sale = |kind: DispositionKind, date: u64| DispositionPayload { kind, date, reason: String::new(), buyer: Some("Abattoir".to_string()) };
+
+        system.set_withdrawal_period(owner(), " Penicillin ".to_string(), Some(WithdrawalPeriod { meat_days: 14, milk_days: 4 })).unwrap();
+        set_time(day(1_000));
+        system.track_medication(vet, 1, "penicillin".to_string(), "10ml".to_string()).unwrap();
+        let withdrawal = system.withdrawal(system.get_animal(1).unwrap());
+        assert_eq!((withdrawal.meat_safe_after, withdrawal.milk_safe_after), (Some(day(1_014)), Some(day(1_004))));
+        let held: Vec<u64> = system.animals_under_withdrawal(day(1_002)).iter().map(|withdrawal| withdrawal.animal_id).collect();
+        assert_eq!(held, vec![1]);
+
+        set_time(day(1_002));
+        assert!(matches!(system.record_milk_sale(owner(), 1, 20.0, "Dairy".to_string()), Err(LivestockError::UnderWithdrawal { .. })));
+        assert!(matches!(system.dispose_animal(owner(), 1, sale(DispositionKind::Sold, day(1_002))), Err(LivestockError::UnderWithdrawal { .. })));
+        assert!(matches!(system.dispose_animal(owner(), 1, sale(DispositionKind::Culled, day(1_002))), Err(LivestockError::UnderWithdrawal { .. })));
+        // the untreated animal is not held back
+        system.record_milk_sale(owner(), 2, 20.0, "Dairy".to_string()).unwrap();
+
+        set_time(day(1_005));
+        system.record_milk_sale(owner(), 1, 20.0, "Dairy".to_string()).unwrap();
+        assert!(matches!(system.dispose_animal(owner(), 1, sale(DispositionKind::Sold, day(1_005))), Err(LivestockError::UnderWithdrawal { .. })));
+        set_time(day(1_015));
+        system.dispose_animal(owner(), 1, sale(DispositionKind::Sold, day(1_015))).unwrap();
+        assert!(system.animals_under_withdrawal(day(1_002)).is_empty());
     }
-}
 
-// Get all animals function to get all the animals in the system
-#[ic_cdk_macros::query]
-fn get_all_animals() -> Vec<Livestock> {
-    ic_cdk::println!("Getting all animals...");
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.animal.values().cloned().collect()
+    #[test]
+    fn callers_need_a_role_that_is_allowed() {
+        let mut system = LivestockManagementSystem::new();
+        let owner = Principal::from_slice(&[1]);
+        let viewer = Principal::from_slice(&[2]);
+        system.roles.insert(owner, Role::Owner);
+        system.roles.insert(viewer, Role::Viewer);
+
+        assert!(system.authorize(owner, &[Role::Owner]).is_ok());
+        assert!(matches!(system.authorize(viewer, ANIMAL_MANAGERS), Err(LivestockError::Unauthorized { .. })));
+        assert!(system.authorize(viewer, READERS).is_ok());
+        // no role, not even reading
+        assert!(matches!(system.authorize(Principal::anonymous(), READERS), Err(LivestockError::Unauthorized { .. })));
+        assert!(matches!(system.authorize(Principal::from_slice(&[3]), READERS), Err(LivestockError::Unauthorized { .. })));
+
+        assert!(matches!(system.grant_role(owner, Principal::anonymous(), Role::Viewer), Err(LivestockError::InvalidInput { .. })));
+        assert!(matches!(system.revoke_role(owner, owner), Err(LivestockError::InvalidInput { .. })));
     }
-}
 
-// Update function to update the animal details by ID
-#[ic_cdk_macros::update]
-fn update_animal(id: u64, age: u8, breed: String, height: f32, healthrecords: String) -> bool {
-    ic_cdk::println!("Updating animal with ID: {}", id);
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_mut().expect("System not Initialized.");
-        match system.animal.get_mut(&(id as u32)) {
-            Some(animal) => {
-                let breed_clone = breed.clone();
-                animal.age = age;
-                animal.breed = breed;
-                animal.height = height;
-                animal.healthrecords = healthrecords;
-                animal.updated_at = Some(0);
-
-                // log the event
-                system.event_logs.push(EventLog {
-                    event_type: "Animal Updated".to_string(),
-                    details: format!("Animal with ID: {}, Breed: {}, Age: {}, Height: {} updated.", id, breed_clone, age, height),
-                    timestamp: time(),
-                });
+    #[test]
+    fn vaccinations_follow_their_protocol() {
+        let mut system = herd(&[1, 2, 3]);
+        let vet = Principal::from_slice(&[2]);
+        let protocol = |id: u64, species: Option<Species>, booster_interval_days: Option<u32>| VaccineProtocol {
+            id,
+            vaccine_name: "Clostridial".to_string(),
+            breed: None,
+            species,
+            first_dose_age_days: 0,
+            doses_required: 2,
+            dose_interval_days: 28,
+            booster_interval_days,
+        };
+        system.vaccine_protocols.insert(1, protocol(1, None, None));
+        system.vaccine_protocols.insert(2, protocol(2, None, Some(365)));
+        system.vaccine_protocols.insert(3, protocol(3, Some(Species::Sheep), None));
+        system.animal.get_mut(&3).unwrap().disposition = Some(Disposition {
+            kind: DispositionKind::Sold,
+            date: time(),
+            reason: String::new(),
+            buyer: Some("Neighbour".to_string()),
+            recorded_by: owner(),
+        });
 
-                ic_cdk::println!("Animal updated: {:?}", animal);
-                true
-            }
-            None => {
-                ic_cdk::println!("No animal found with ID: {}", id);
-                false
+        // a third dose is only given when the protocol has boosters
+        for protocol_id in [1, 2] {
+            for dose_number in [1, 2] {
+                assert_eq!(system.record_vaccination(vet, 1, protocol_id).unwrap().dose_number, dose_number);
             }
         }
+        assert!(matches!(system.record_vaccination(vet, 1, 1), Err(LivestockError::InvalidInput { .. })));
+        assert_eq!(system.record_vaccination(vet, 1, 2).unwrap().dose_number, 3);
+
+        assert!(matches!(system.record_vaccination(vet, 2, 3), Err(LivestockError::InvalidInput { .. })));
+        assert!(matches!(system.record_vaccination(vet, 3, 1), Err(LivestockError::InvalidInput { .. })));
+        assert!(system.get_animal(2).unwrap().vaccinations.is_empty() && system.get_animal(3).unwrap().vaccinations.is_empty());
     }
-}
 
+    #[test]
+    fn disposed_animals_leave_the_active_herd() {
+        let mut system = herd(&[1, 2]);
+        let vet = Principal::from_slice(&[2]);
+        system.roles.insert(vet, Role::Vet);
+        system.vaccine_protocols.insert(1, VaccineProtocol {
+            id: 1,
+            vaccine_name: "Clostridial".to_string(),
+            breed: None,
+            species: None,
+            first_dose_age_days: 0,
+            doses_required: 1,
+            dose_interval_days: 0,
+            booster_interval_days: None,
+        });
+        for id in [1, 2] {
+            let course = MedicationPayload {
+                name: "Meloxicam".to_string(),
+                dosage: "5ml".to_string(),
+                start_date: time() - 2 * NANOS_PER_DAY,
+                end_date: time() + 5 * NANOS_PER_DAY,
+                frequency_hours: 24,
+                route: AdministrationRoute::Other,
+                prescribed_by: vet,
+            };
+            system.schedule_medication(vet, id, course).unwrap();
+        }
+        let alert_id = system.raise_alert(2, AlertKind::StatusChange(HealthStatus::Sick), AlertSeverity::Medium, "Health status changed to Sick".to_string(), time());
+
+        let died = DispositionPayload { kind: DispositionKind::Died, date: time(), reason: "Bloat".to_string(), buyer: None };
+        let sold = DispositionPayload { kind: DispositionKind::Sold, date: time(), reason: String::new(), buyer: None };
+        assert!(matches!(system.dispose_animal(owner(), 2, sold), Err(LivestockError::InvalidInput { .. })));
+        system.dispose_animal(vet, 2, died.clone()).unwrap();
+        assert!(matches!(system.dispose_animal(vet, 2, died), Err(LivestockError::InvalidInput { .. })));
 
-// Function to update the animal health status
-#[ic_cdk_macros::update]
-fn update_health_status(id: u64, new_status: HealthStatus) -> bool {
+        // the record stays for reporting, its open alerts are closed
+        assert_eq!(system.get_animal(2).unwrap().disposition.as_ref().map(|disposition| disposition.kind), Some(DispositionKind::Died));
+        assert!(!system.health_alerts.iter().any(|alert| alert.id == alert_id && alert.is_open()));
+        let disposed: Vec<u64> = system.disposed_animals(Some(DispositionKind::Died), None, None, None, None).unwrap().animals.iter().map(|animal| animal.id).collect();
+        assert_eq!(disposed, vec![2]);
+        assert_eq!(system.get_total_animals(), 1);
 
-    ic_cdk::println!("Updating health status of animal with ID: {} to {:?}", id, new_status);
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_mut().expect("System not Initialized.");
-        if let Some(animal) = system.animal.get_mut(&(id as u32)) {
-            animal.healthstatus = new_status;
-            animal.healthrecords = format!("{:?}", new_status);
-            animal.updated_at = Some(time());
-
-            // Check if the new status is Critical or Sick and create an alert
-            if matches!(new_status, HealthStatus::Critical | HealthStatus::Sick | HealthStatus::Recovering) {
-                let alert = HealthAlert {
-                    animal_id: id,
-                    status: new_status,
-                    timestamp: time(),
-                };
-                system.health_alerts.push(alert);
-                ic_cdk::println!("ALERT: Animal with ID: {} is now {:?}", id, new_status);
-            }
-            true
-        } else {
-            ic_cdk::println!("No animal found with ID: {}", id);
-            false
-        }
+        let due: Vec<u64> = system.due_vaccinations(0, time()).iter().map(|vaccination| vaccination.animal_id).collect();
+        assert_eq!(due, vec![1]);
+        assert_eq!(system.raise_overdue_vaccination_alerts(time()), 1);
+        let active: Vec<u64> = system.find_medications(|medication| medication.is_active(time())).iter().map(|medication| medication.animal_id).collect();
+        assert_eq!(active, vec![1]);
+        let overdue: Vec<u64> = system.find_medications(|medication| medication.is_overdue(time())).iter().map(|medication| medication.animal_id).collect();
+        assert_eq!(overdue, vec![1]);
     }
-}
 
+    #[test]
+    fn only_animals_without_history_can_be_deleted() {
+        let mut system = family();
+        system.animal.insert(5, Livestock { sex: Sex::Male, ..animal(5, "Angus") });
+        system.animal.insert(6, animal(6, "Angus"));
+        system.animal.insert(7, animal(7, "Angus"));
+        system.groups.insert(1, HerdGroup { id: 1, name: "Lot".to_string(), kind: GroupKind::Lot, member_ids: vec![6, 7], created_at: 0 });
+        let service = system.record_service(owner(), 4, 5, time() - NANOS_PER_DAY, ServiceMethod::Natural).unwrap();
+        system.record_pregnancy_check(owner(), 4, service.id, false).unwrap();
+        let alert_id = system.raise_alert(6, AlertKind::StatusChange(HealthStatus::Sick), AlertSeverity::Medium, "Health status changed to Sick".to_string(), time());
+        system.resolve_alert(owner(), alert_id).unwrap();
 
-// ToString implementation for HealthStatus
-impl ToString for HealthStatus {
-    fn to_string(&self) -> String {
-        match self {
-            HealthStatus::Healthy => "Healthy".to_string(),
-            HealthStatus::Sick => "Sick".to_string(),
-            HealthStatus::Critical => "Critical".to_string(),
-            HealthStatus::Recovering => "Recovering".to_string(),
+        // a parent, a served dam, the sire of a finished service and an animal with a resolved alert all have history
+        for id in [1, 4, 5, 6] {
+            assert!(matches!(system.delete_animal(owner(), id), Err(LivestockError::InvalidInput { .. })));
+            assert!(system.get_animal(id).is_ok());
         }
+        assert!(system.health_alerts.iter().any(|alert| alert.id == alert_id));
+
+        system.delete_animal(owner(), 7).unwrap();
+        assert!(system.get_animal(7).is_err());
+        assert_eq!(system.groups[&1].member_ids, vec![6]);
     }
-    
-}
 
+    #[test]
+    fn ids_above_u32_are_not_aliased() {
+        let system = herd(&[LOW_ID, HIGH_ID]);
 
-// Function to get the health alerts
-#[ic_cdk_macros::query]
-fn get_health_alerts() -> Vec<HealthAlert> {
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.health_alerts.clone()
+        assert_eq!(system.get_animal(LOW_ID).unwrap().breed, "breed 1");
+        assert_eq!(system.get_animal(HIGH_ID).unwrap().breed, format!("breed {}", HIGH_ID));
+        // 2^32 used to alias onto ID 0
+        assert!(matches!(system.get_animal(1 << 32), Err(LivestockError::NotFound { .. })));
+        assert!(matches!(system.get_animal(u64::MAX), Err(LivestockError::NotFound { .. })));
     }
-}
 
-// Medication tracking function
-#[ic_cdk_macros::update]
-fn track_medication(animal_id: u64, medication_name: String, dosage: String) -> bool {
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_mut().expect("System not Initialized.");
-        if let Some(animal) = system.animal.get_mut(&(animal_id as u32)) {
-            let medication = Medication {
-                id: animal.medical_records.len() as u64 + 1,
-                name: medication_name,
-                dosage,
-                start_date: time(),
-                end_date: time() + 86400, // 1 day
-            };
-            animal.medical_records.push(medication);
-            ic_cdk::println!("Medication tracked for animal with ID: {}", animal_id);
-            true
-        } else {
-            false
-            
-        }
+    #[test]
+    fn ids_are_allocated_past_u32_max() {
+        let mut system = LivestockManagementSystem::new();
+        system.next_id = u32::MAX as u64;
+
+        assert_eq!(system.allocate_animal_id().unwrap(), u32::MAX as u64);
+        assert_eq!(system.allocate_animal_id().unwrap(), 1 << 32);
+        assert_eq!(system.next_id, (1 << 32) + 1);
     }
-}
 
-// A function to retrieve all the animals whose Health status is Critical
-#[ic_cdk_macros::query]
-fn get_critical_animals() -> Vec<Livestock> {
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.animal.values().filter(|animal| animal.healthstatus == HealthStatus::Critical).cloned().collect()
+    #[test]
+    fn ids_run_out_instead_of_wrapping() {
+        let mut system = LivestockManagementSystem::new();
+        system.next_id = u64::MAX;
+
+        assert!(matches!(system.allocate_animal_id(), Err(LivestockError::InvalidInput { .. })));
+        assert_eq!(system.next_id, u64::MAX);
     }
-}
 
-// A function to retrieve all the animals whose Health status is Sick
-#[ic_cdk_macros::query]
-fn get_sick_animals() -> Vec<Livestock> {
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.animal.values().filter(|animal| animal.healthstatus == HealthStatus::Sick).cloned().collect()
+    #[test]
+    fn large_ids_page_in_order() {
+        let system = herd(&[HIGH_ID, LOW_ID, 1 << 33]);
+        let sort = AnimalSort::default();
+
+        let first = system.list_animals(&AnimalFilter::default(), sort, None, Some(2), 0).unwrap();
+        let ids: Vec<u64> = first.animals.iter().map(|animal| animal.id).collect();
+        assert_eq!(ids, vec![LOW_ID, HIGH_ID]);
+        assert_eq!(first.total, 3);
+
+        let second = system.list_animals(&AnimalFilter::default(), sort, first.next_cursor, Some(2), 0).unwrap();
+        let ids: Vec<u64> = second.animals.iter().map(|animal| animal.id).collect();
+        assert_eq!(ids, vec![1 << 33]);
+        assert!(second.next_cursor.is_none());
     }
-}
 
-// A function to retrieve all the animals whose Health status is Recovering
-#[ic_cdk_macros::query]
-fn get_recovering_animals() -> Vec<Livestock> {
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.animal.values().filter(|animal| animal.healthstatus == HealthStatus::Recovering).cloned().collect()
+    #[test]
+    fn pedigree_follows_parents_above_u32() {
+        let mut system = herd(&[LOW_ID, HIGH_ID]);
+        let mut calf = animal(HIGH_ID + 1, "calf");
+        calf.parent_ids = Some(ParentIds { parent1_id: LOW_ID, parent2_id: HIGH_ID });
+        system.animal.insert(calf.id, calf);
+
+        let pedigree = system.get_pedigree(HIGH_ID + 1, 1).unwrap();
+        let parents: Vec<u64> = pedigree.parents.iter().map(|node| node.animal.id).collect();
+        assert_eq!(parents, vec![LOW_ID, HIGH_ID]);
+
+        let descendants = system.get_descendants(HIGH_ID, 1).unwrap();
+        assert_eq!(descendants.len(), 1);
+        assert_eq!(descendants[0].animal.id, HIGH_ID + 1);
+        assert_eq!(descendants[0].animal.breed, "calf");
     }
-}
 
-// A function to retrieve all the animals whose Health status is Healthy
-#[ic_cdk_macros::query]
-fn get_healthy_animals() -> Vec<Livestock> {
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.animal.values().filter(|animal| animal.healthstatus == HealthStatus::Healthy).cloned().collect()
+    fn identifier(kind: IdentifierKind, value: &str, retired: bool) -> Identifier {
+        Identifier {
+            kind,
+            value: value.to_string(),
+            assigned_at: 0,
+            assigned_by: Principal::anonymous(),
+            retired_at: retired.then_some(1),
+            retired_by: None,
+            retire_reason: String::new(),
+        }
     }
-}
 
-// Query function to get all the event logs
-#[ic_cdk_macros::query]
-fn get_event_logs() -> Vec<EventLog> {
-    ic_cdk::println!("Getting all event logs...");
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.event_logs.clone()
+    #[test]
+    fn identifiers_are_normalized() {
+        assert_eq!(normalize_identifier(IdentifierKind::Rfid, " 982 000 123456789 ").unwrap(), "982000123456789");
+        assert_eq!(normalize_identifier(IdentifierKind::VisualTag, " a-12 ").unwrap(), "A-12");
+        assert!(matches!(normalize_identifier(IdentifierKind::Microchip, "98A1"), Err(LivestockError::InvalidInput { .. })));
+        assert!(matches!(normalize_identifier(IdentifierKind::NationalId, "  "), Err(LivestockError::InvalidInput { .. })));
     }
-}
 
+    #[test]
+    fn retired_identifiers_still_find_the_animal() {
+        let mut system = herd(&[LOW_ID, HIGH_ID]);
+        let tagged = system.animal.get_mut(&HIGH_ID).unwrap();
+        tagged.identifiers.push(identifier(IdentifierKind::VisualTag, "A12", true));
+        tagged.identifiers.push(identifier(IdentifierKind::VisualTag, "A13", false));
 
-// Get total number of animals query
-#[ic_cdk_macros::query]
-fn get_total_animals() -> u64 {
-    ic_cdk::println!("Getting total number of animals...");
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.get_total_animals()
+        assert_eq!(system.find_animal_by_identifier(IdentifierKind::VisualTag, "a12").unwrap().id, HIGH_ID);
+        assert_eq!(system.find_animal_by_identifier(IdentifierKind::VisualTag, "A13").unwrap().id, HIGH_ID);
+        assert!(system.find_animal_by_identifier(IdentifierKind::Rfid, "12").is_err());
+        assert!(system.check_identifier_unused(IdentifierKind::VisualTag, "A12").is_err());
+        assert!(system.check_identifier_unused(IdentifierKind::NationalId, "A12").is_ok());
     }
-}
 
-// Get average age of all the animals query
-#[ic_cdk_macros::query]
-fn get_average_age() -> f32 {
-    ic_cdk::println!("Getting average age of all the animals...");
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.get_average_age()
+    fn location(id: u64, kind: LocationKind, parent_id: Option<u64>, capacity: Option<u32>) -> Location {
+        Location {
+            id,
+            name: format!("{:?} {}", kind, id),
+            kind,
+            parent_id,
+            capacity,
+        }
     }
-}
 
-// Get average height of all the animals query
-#[ic_cdk_macros::query]
-fn get_average_height() -> f32 {
-    ic_cdk::println!("Getting average height of all the animals...");
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.get_average_height()
-    }
-}
+    #[test]
+    fn barn_capacity_counts_its_pens() {
+        let mut system = herd(&[1, 2, 3]);
+        system.locations.insert(10, location(10, LocationKind::Barn, None, Some(2)));
+        system.locations.insert(11, location(11, LocationKind::Pen, Some(10), None));
+        system.locations.insert(12, location(12, LocationKind::Pen, Some(10), None));
+        system.animal.get_mut(&1).unwrap().location_id = Some(11);
+        system.animal.get_mut(&2).unwrap().location_id = Some(12);
 
-// Get number of animals per breed query
-#[ic_cdk_macros::query]
-fn get_animals_per_breed() -> HashMap<String, u64> {
-    ic_cdk::println!("Getting number of animals per breed...");
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.get_animals_per_breed()
-    }
-}
+        assert_eq!(system.occupancy(10), 2);
+        assert_eq!(system.occupancy(11), 1);
+        assert_eq!(system.animals_in_location(10, false, None, None).unwrap().total, 0);
+        assert_eq!(system.animals_in_location(10, true, None, None).unwrap().total, 2);
+        // The pens have room but the barn around them is full
+        let moved = system.move_animals(Principal::anonymous(), vec![3], Some(11));
+        assert!(matches!(moved, Err(LivestockError::InvalidInput { .. })));
+        assert_eq!(system.get_animal(3).unwrap().location_id, None);
 
-// Get health status statistics of all the animals query
-#[ic_cdk_macros::query]
-fn get_health_status_statistics() -> HashMap<HealthStatus, u64> {
-    ic_cdk::println!("Getting health status statistics of all the animals...");
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_ref().expect("System not Initialized.");
-        system.get_health_status_statistics()
+        let filter = AnimalFilter { location_id: Some(10), ..Default::default() };
+        let page = system.list_animals(&filter, AnimalSort::default(), None, None, 0).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(system.get_animals_per_breed(Some(12)).len(), 1);
     }
-}
 
-// Delete function to delete the animal by ID
-#[ic_cdk_macros::update]
-fn delete_animal(id: u64) -> bool {
-    ic_cdk::println!("Deleting animal with ID: {}", id);
-    unsafe {
-        let system = LIVECTOCK_SYSTEM.as_mut().expect("System not Initialized.");
-        match system.animal.remove(&(id as u32)) {
-            Some(animal) => {
-                ic_cdk::println!("Animal deleted: {:?}", animal);
-                system.event_logs.push(EventLog {
-                    event_type: "Animal Deleted".to_string(),
-                    details: format!("Animal with ID: {} deleted.", id),
-                    timestamp: time(),
-                });
+    #[test]
+    fn batches_report_each_animal() {
+        let mut system = herd(&[1, 2, 3, 4]);
+        system.animal.get_mut(&4).unwrap().disposition = Some(Disposition {
+            kind: DispositionKind::Sold,
+            date: 0,
+            reason: String::new(),
+            buyer: Some("Neighbour".to_string()),
+            recorded_by: owner(),
+        });
+        system.groups.insert(1, HerdGroup {
+            id: 1,
+            name: "Calves".to_string(),
+            kind: GroupKind::Cohort,
+            member_ids: vec![1, 2, 4],
+            created_at: 0,
+        });
+        // Animals that left the herd are skipped when acting on a group
+        assert_eq!(system.batch_animal_ids(BatchTarget::Group(1)).unwrap(), vec![1, 2]);
+        assert_eq!(system.batch_animal_ids(BatchTarget::Animals(vec![3, 1, 3])).unwrap(), vec![1, 3]);
+        assert!(system.batch_animal_ids(BatchTarget::Animals((0..=MAX_BATCH_SIZE as u64).collect())).is_err());
 
-                system.animal.remove(&(id as u32));
-                ic_cdk::println!("Animal deleted with ID: {}", id);
-                true
-            }
-            None => {
-                ic_cdk::println!("No animal found with ID: {}", id);
-                false
-            }
-        }
+        system.locations.insert(10, location(10, LocationKind::Pen, None, Some(1)));
+        system.animal.get_mut(&3).unwrap().location_id = Some(10);
+        let outcomes = system.batch_move_animals(Principal::anonymous(), BatchTarget::Animals(vec![1, 99]), Some(10)).unwrap();
+        assert_eq!(outcomes.len(), 2);
+        assert!(matches!(outcomes[0].error, Some(LivestockError::InvalidInput { .. })));
+        assert!(matches!(outcomes[1].error, Some(LivestockError::NotFound { .. })));
+        assert!(matches!(system.batch_move_animals(Principal::anonymous(), BatchTarget::Group(2), None), Err(LivestockError::NotFound { .. })));
     }
-}
 
+    #[test]
+    fn feed_report_splits_deliveries_and_weighs_gain() {
+        let mut system = herd(&[1, 2]);
+        for (id, first, last) in [(1, 100.0, 110.0), (2, 200.0, 230.0)] {
+            let weighing = |measured_at: u64, weight: f32| Measurement { measured_at, weight: Some(weight), height: None, body_condition_score: None, recorded_by: None };
+            system.animal.get_mut(&id).unwrap().measurements = vec![weighing(NANOS_PER_DAY, first), weighing(10 * NANOS_PER_DAY, last)];
+        }
+        system.groups.insert(1, HerdGroup { id: 1, name: "Lot".to_string(), kind: GroupKind::Lot, member_ids: vec![1, 2], created_at: 0 });
+        let delivery = |id: u64, quantity_kg: f64, date: u64, target: FeedTarget, animal_ids: Vec<u64>| FeedDelivery {
+            id,
+            feed_id: 1,
+            quantity_kg,
+            cost: quantity_kg * 0.5,
+            date: date * NANOS_PER_DAY,
+            target,
+            animal_ids,
+            ration_id: Some(1),
+            recorded_by: Principal::anonymous(),
+        };
+        system.feed_deliveries = vec![
+            delivery(1, 100.0, 1, FeedTarget::Group(1), vec![1, 2]),
+            delivery(2, 100.0, 10, FeedTarget::Group(1), vec![1, 2]),
+            delivery(3, 40.0, 5, FeedTarget::Animal(2), vec![2]),
+        ];
+
+        let group = system.feed_report(FeedTarget::Group(1), None, None).unwrap();
+        assert_eq!(group.head_count, 2);
+        assert_eq!(group.feed_kg, 200.0);
+        assert_eq!(group.cost_per_head, 50.0);
+        assert_eq!(group.weight_gain_kg, 40.0);
+        assert_eq!(group.feed_conversion_ratio, Some(5.0));
 
+        // The animal's share of the group feed plus its own
+        let animal = system.feed_report(FeedTarget::Animal(2), None, None).unwrap();
+        assert_eq!(animal.feed_kg, 140.0);
+        assert_eq!(animal.cost, 70.0);
+        assert_eq!(animal.feed_conversion_ratio, Some(140.0 / 30.0));
 
-// Export the candid functions
+        // A single weighing in the range gives no gain
+        let early = system.feed_report(FeedTarget::Group(1), None, Some(5 * NANOS_PER_DAY)).unwrap();
+        assert_eq!(early.feed_kg, 100.0);
+        assert_eq!(early.weighed_head_count, 0);
+        assert_eq!(early.feed_conversion_ratio, None);
+        assert!(matches!(system.feed_report(FeedTarget::Group(2), None, None), Err(LivestockError::NotFound { .. })));
+    }
 
-ic_cdk::export_candid!(); 
+    #[test]
+    fn milkings_build_lactations_totals_and_mastitis_alerts() {
+        let mut system = herd(&[1, 2]);
+        let milking = |id: u64, milked_at: u64, volume_litres: f64, fat_percent: Option<f32>, somatic_cell_count: Option<u32>| MilkRecord {
+            id,
+            milked_at,
+            volume_litres,
+            fat_percent,
+            protein_percent: None,
+            somatic_cell_count,
+            withheld: id == 4,
+            recorded_by: Principal::anonymous(),
+        };
+        let calving = 10 * NANOS_PER_DAY;
+        let cow = system.animal.get_mut(&1).unwrap();
+        cow.reproductive_status = ReproductiveStatus::Lactating { since: calving };
+        cow.milk_records = vec![
+            milking(1, calving - NANOS_PER_DAY, 5.0, None, None),
+            milking(2, calving + NANOS_PER_DAY, 10.0, Some(4.0), None),
+            milking(3, calving + NANOS_PER_DAY + 12 * NANOS_PER_HOUR, 12.0, Some(3.0), None),
+            milking(4, calving + 400 * NANOS_PER_DAY, 8.0, None, None),
+        ];
+        system.animal.get_mut(&2).unwrap().milk_records = vec![milking(5, calving + NANOS_PER_DAY, 8.0, None, Some(100_000))];
+
+        let curve = system.lactation_curve(1, None, calving + 500 * NANOS_PER_DAY).unwrap();
+        assert_eq!(curve.days_in_milk, 500);
+        assert_eq!(curve.milkings, 3);
+        assert_eq!(curve.total_litres, 30.0);
+        assert_eq!(curve.yield_305_days, 22.0);
+        assert_eq!((curve.peak_day, curve.peak_litres), (Some(1), 22.0));
+        assert!(matches!(system.lactation_curve(1, Some(calving + 1), 0), Err(LivestockError::NotFound { .. })));
+        assert!(matches!(system.lactation_curve(2, None, 0), Err(LivestockError::NotFound { .. })));
 
+        let totals = system.herd_milk_totals(Some(calving), None);
+        assert_eq!(totals.len(), 2);
+        assert_eq!((totals[0].day, totals[0].animals, totals[0].milkings, totals[0].litres), (calving + NANOS_PER_DAY, 2, 3, 30.0));
+        // 10 l at 4% and 12 l at 3%, the unsampled milking left out
+        assert_eq!(totals[0].fat_percent, Some(76.0 / 22.0));
+        assert_eq!(totals[0].somatic_cell_count, Some(100_000.0));
+        assert_eq!(totals[1].withheld_litres, 8.0);
 
+        // one open alert per animal, high severity from twice the threshold
+        assert_eq!(system.raise_mastitis_alert(2, &milking(6, 0, 8.0, None, Some(150_000)), 0), None);
+        let alert_id = system.raise_mastitis_alert(2, &milking(7, 0, 8.0, None, Some(400_000)), 0).unwrap();
+        assert_eq!(system.raise_mastitis_alert(2, &milking(8, 0, 8.0, None, Some(250_000)), 0), None);
+        assert_eq!(system.open_alerts(None)[0].severity, AlertSeverity::High);
+        system.health_alerts[0].resolved_at = Some(1);
+        let next_id = system.raise_mastitis_alert(2, &milking(9, 0, 8.0, None, Some(250_000)), 0).unwrap();
+        assert_ne!(next_id, alert_id);
+        let open = system.open_alerts(None);
+        assert_eq!((open.len(), open[0].id, open[0].severity), (1, next_id, AlertSeverity::Medium));
+    }
+}